@@ -0,0 +1,634 @@
+#![no_std]
+
+//! Handoff structures shared between `uefi_loader` and the kernel.
+//!
+//! Anything in here is read by one binary and written by another, so every struct is `#[repr(C)]`
+//! and any change to the layout of [`UEFIBootInfo`] must bump [`BOOT_INFO_VERSION`].
+
+use core::fmt::{Display, Formatter};
+
+/// `"GROVEBI\0"` read as a little endian `u64`
+pub const BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"GROVEBI\0");
+pub const BOOT_INFO_VERSION: u32 = 13;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// UEFI memory type (from the OS-defined range) the loader uses for the pages holding the kernel image
+pub const KERNEL_MEMORY_TYPE: u32 = 0x8000_0000;
+/// UEFI memory type the loader uses for the pages holding boot modules
+pub const MODULE_MEMORY_TYPE: u32 = 0x8000_0001;
+/// UEFI memory type the loader uses for its page tables, which the kernel keeps using for the higher half
+pub const PAGE_TABLE_MEMORY_TYPE: u32 = 0x8000_0002;
+
+/// Maximum length of a boot module name in bytes
+pub const MODULE_NAME_LEN: usize = 64;
+
+/// Name of the UEFI variable holding the [`BootState`]
+pub const BOOT_STATE_VARIABLE: &str = "GroveBootState";
+/// Vendor GUID of the GroveOS UEFI variables (`f6adbe04-9a78-4822-a6a0-31827fc43787`), in the byte order UEFI stores it
+pub const GROVE_VENDOR_GUID: [u8; 16] = [
+    0x04, 0xbe, 0xad, 0xf6, 0x78, 0x9a, 0x22, 0x48, 0xa6, 0xa0, 0x31, 0x82, 0x7f, 0xc4, 0x37, 0x87,
+];
+/// How many times a newly installed kernel is tried before the loader goes back to the previous one
+pub const DEFAULT_BOOT_TRIES: u32 = 3;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BootInfoError {
+    NullPointer,
+    BadMagic(u64),
+    VersionMismatch { expected: u32, found: u32 },
+    Truncated { expected: u32, found: u32 },
+}
+
+impl Display for BootInfoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BootInfoError::NullPointer => write!(f, "boot info pointer is null"),
+            BootInfoError::BadMagic(magic) => write!(f, "bad boot info magic {:#x}", magic),
+            BootInfoError::VersionMismatch { expected, found } => write!(
+                f,
+                "boot info version mismatch (kernel expects {}, loader passed {})",
+                expected, found
+            ),
+            BootInfoError::Truncated { expected, found } => write!(
+                f,
+                "boot info is too small (kernel expects {} bytes, loader passed {})",
+                expected, found
+            ),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct BootInfoHeader {
+    magic: u64,
+    version: u32,
+    size: u32,
+}
+
+impl BootInfoHeader {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+/// How the color channels are laid out in a 32-bit framebuffer pixel
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PixelFormat {
+    /// Red in the lowest byte, then green and blue
+    Rgb,
+    /// Blue in the lowest byte, then green and red
+    Bgr,
+    /// Channels are described by [`Framebuffer::bitmask`]
+    Bitmask,
+}
+
+/// The bits used by each channel of a pixel, only meaningful for [`PixelFormat::Bitmask`]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PixelBitmask {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Framebuffer {
+    pub base: *mut u32,
+    /// Length of the framebuffer in pixels
+    pub size: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Pixels per scan line, which can be more than `width`
+    pub stride: usize,
+    pub format: PixelFormat,
+    pub bitmask: PixelBitmask,
+}
+
+impl Framebuffer {
+    pub const fn empty() -> Self {
+        Self {
+            base: core::ptr::null_mut(),
+            size: 0,
+            width: 0,
+            height: 0,
+            stride: 0,
+            format: PixelFormat::Bgr,
+            bitmask: PixelBitmask {
+                red: 0,
+                green: 0,
+                blue: 0,
+                reserved: 0,
+            },
+        }
+    }
+
+    /// Size of the framebuffer in bytes
+    pub fn byte_len(&self) -> usize {
+        self.size * size_of::<u32>()
+    }
+
+    /// # Safety
+    /// `base` must still be mapped and nothing else may be holding a reference to the framebuffer.
+    pub unsafe fn as_slice(&self) -> &'static mut [u32] {
+        // There's no framebuffer when the loader couldn't find a usable graphics mode
+        if self.base.is_null() {
+            return &mut [];
+        }
+
+        unsafe { core::slice::from_raw_parts_mut(self.base, self.size) }
+    }
+
+    /// Converts a `0xRRGGBB` color into the pixel value for this framebuffer
+    pub fn encode_color(&self, color: u32) -> u32 {
+        let [blue, green, red, _] = color.to_le_bytes();
+
+        match self.format {
+            PixelFormat::Rgb => u32::from_le_bytes([red, green, blue, 0]),
+            PixelFormat::Bgr => u32::from_le_bytes([blue, green, red, 0]),
+            PixelFormat::Bitmask => {
+                encode_channel(red, self.bitmask.red)
+                    | encode_channel(green, self.bitmask.green)
+                    | encode_channel(blue, self.bitmask.blue)
+            }
+        }
+    }
+}
+
+/// Scales an 8-bit channel value to the width of `mask` and moves it into place
+fn encode_channel(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let bits = mask.count_ones();
+    let value = if bits <= 8 {
+        value as u32 >> (8 - bits)
+    } else {
+        (value as u32) << (bits - 8)
+    };
+
+    (value << mask.trailing_zeros()) & mask
+}
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MemoryRegionKind {
+    /// Free memory that the kernel can use right away
+    Usable,
+    /// Memory allocated by the loader, including the boot info itself
+    LoaderData,
+    /// Boot services code and data, free to use once everything needed has been copied out of the boot info
+    BootServices,
+    /// Memory used by the UEFI runtime services, must never be reused
+    RuntimeServices,
+    /// ACPI tables, can be reused once the kernel has finished reading them
+    AcpiReclaimable,
+    /// Firmware memory that has to be preserved across ACPI sleep states
+    AcpiNvs,
+    Mmio,
+    /// The kernel image loaded by the loader
+    Kernel,
+    /// Files loaded by the loader for the kernel (initrd, modules), see [`BootModule`]
+    BootModule,
+    /// Page tables built by the loader, the higher half of every kernel page table points into them
+    PageTables,
+    /// Anything else (reserved, unusable, persistent memory...), never touched by the kernel
+    Reserved,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub page_count: u64,
+    pub kind: MemoryRegionKind,
+}
+
+impl MemoryRegion {
+    pub fn end(&self) -> u64 {
+        self.start + self.page_count * PAGE_SIZE
+    }
+}
+
+/// A file the loader put in memory for the kernel, such as the initrd or a driver module
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BootModule {
+    /// Page aligned physical address of the file contents
+    pub phys_addr: u64,
+    /// Size of the file in bytes, the rest of the last page is zeroed
+    pub size: u64,
+    /// UTF-8 name, padded with zeroes
+    pub name: [u8; MODULE_NAME_LEN],
+}
+
+impl BootModule {
+    pub const fn empty() -> Self {
+        Self {
+            phys_addr: 0,
+            size: 0,
+            name: [0; MODULE_NAME_LEN],
+        }
+    }
+
+    /// Builds a module descriptor, truncating `name` to [`MODULE_NAME_LEN`] bytes if needed
+    pub fn new(phys_addr: u64, size: u64, name: &str) -> Self {
+        let mut len = name.len().min(MODULE_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut module = Self {
+            phys_addr,
+            size,
+            name: [0; MODULE_NAME_LEN],
+        };
+        module.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+        module
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(MODULE_NAME_LEN);
+
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.size.div_ceil(PAGE_SIZE)
+    }
+}
+
+/// One of the two kernel slots on the ESP
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BootSlot {
+    /// The kernel wasn't booted from a slot
+    None = 0,
+    A = 1,
+    B = 2,
+}
+
+impl BootSlot {
+    /// The slot that isn't this one, `None` stays `None`
+    pub const fn other(self) -> Self {
+        match self {
+            BootSlot::None => BootSlot::None,
+            BootSlot::A => BootSlot::B,
+            BootSlot::B => BootSlot::A,
+        }
+    }
+
+    const fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(BootSlot::None),
+            1 => Some(BootSlot::A),
+            2 => Some(BootSlot::B),
+            _ => None,
+        }
+    }
+}
+
+/// A/B slot state, kept in the [`BOOT_STATE_VARIABLE`] UEFI variable.
+///
+/// `active` is the slot that's known to boot. When the kernel installs an update into the other slot, it sets
+/// `pending` to that slot and `tries_left` to the number of attempts it gets. The loader boots the pending slot
+/// while there are tries left, taking one each time, and goes back to `active` once they run out. The kernel
+/// confirms a boot from the pending slot by making it the active slot.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BootState {
+    pub active: BootSlot,
+    pub pending: BootSlot,
+    pub tries_left: u32,
+}
+
+impl BootState {
+    /// Version of the serialized layout, stored in the first 4 bytes
+    const VERSION: u32 = 1;
+    pub const SIZE: usize = 16;
+
+    /// The state used when the variable doesn't exist yet: boot slot A, nothing pending
+    pub const fn initial() -> Self {
+        Self {
+            active: BootSlot::A,
+            pending: BootSlot::None,
+            tries_left: 0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&Self::VERSION.to_le_bytes());
+        bytes[4..8].copy_from_slice(&(self.active as u32).to_le_bytes());
+        bytes[8..12].copy_from_slice(&(self.pending as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.tries_left.to_le_bytes());
+
+        bytes
+    }
+
+    /// Parses a serialized state, `None` if it's from another version or doesn't make sense
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let word = |index: usize| {
+            let bytes = bytes.get(index * 4..index * 4 + 4)?;
+            Some(u32::from_le_bytes(bytes.try_into().ok()?))
+        };
+
+        if word(0)? != Self::VERSION {
+            return None;
+        }
+
+        let state = Self {
+            active: BootSlot::from_u32(word(1)?)?,
+            pending: BootSlot::from_u32(word(2)?)?,
+            tries_left: word(3)?,
+        };
+
+        let valid = state.active != BootSlot::None
+            && (state.pending == BootSlot::None || state.pending == state.active.other());
+        valid.then_some(state)
+    }
+}
+
+/// A processor reported by the firmware's MP services
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ProcessorInfo {
+    /// The processor's local APIC ID (its x2APIC ID on systems that use x2APIC mode)
+    pub apic_id: u32,
+    /// See the `PROCESSOR_` constants
+    pub flags: u32,
+    /// Where the processor sits in the topology, as reported by the firmware
+    pub package: u32,
+    pub core: u32,
+    pub thread: u32,
+}
+
+impl ProcessorInfo {
+    /// The processor the loader (and so the kernel) runs on
+    pub const PROCESSOR_BSP: u32 = 1 << 0;
+    /// The processor can be started, disabled processors should be left alone
+    pub const PROCESSOR_ENABLED: u32 = 1 << 1;
+    /// The processor passed the firmware's self test
+    pub const PROCESSOR_HEALTHY: u32 = 1 << 2;
+
+    pub const fn empty() -> Self {
+        Self {
+            apic_id: 0,
+            flags: 0,
+            package: 0,
+            core: 0,
+            thread: 0,
+        }
+    }
+
+    pub fn is_bsp(&self) -> bool {
+        self.flags & Self::PROCESSOR_BSP != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.flags & Self::PROCESSOR_ENABLED != 0
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.flags & Self::PROCESSOR_HEALTHY != 0
+    }
+}
+
+/// TSC readings the loader takes at the end of each of its phases, for boot time profiling. The loader doesn't know
+/// the TSC's frequency, so these are raw cycle counts.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LoaderTimestamps {
+    /// When the loader was started
+    pub loader_entry: u64,
+    /// After the config was read and an entry was picked, which includes the time the menu was open
+    pub entry_selected: u64,
+    /// After the kernel was read, decompressed and verified
+    pub kernel_read: u64,
+    /// After the kernel's segments were loaded and relocated
+    pub kernel_loaded: u64,
+    /// After everything the boot info points to was set up
+    pub boot_info_ready: u64,
+    /// Right before exiting boot services
+    pub exit_boot_services: u64,
+}
+
+impl LoaderTimestamps {
+    pub const fn empty() -> Self {
+        Self {
+            loader_entry: 0,
+            entry_selected: 0,
+            kernel_read: 0,
+            kernel_loaded: 0,
+            boot_info_ready: 0,
+            exit_boot_services: 0,
+        }
+    }
+
+    /// The name of each phase with the timestamp at its end, in order. The first phase starts at `loader_entry`.
+    pub fn phases(&self) -> [(&'static str, u64); 5] {
+        [
+            ("config and menu", self.entry_selected),
+            ("kernel read", self.kernel_read),
+            ("kernel load", self.kernel_loaded),
+            ("boot info", self.boot_info_ready),
+            ("memory map and page tables", self.exit_boot_services),
+        ]
+    }
+}
+
+#[repr(C)]
+pub struct UEFIBootInfo {
+    header: BootInfoHeader,
+
+    /// Virtual address at which all physical memory is mapped, physical address `x` is at `hhdm_offset + x`
+    pub hhdm_offset: u64,
+
+    /// How far above its link address the kernel was loaded, 0 if KASLR is off
+    pub kernel_slide: u64,
+
+    /// The kernel's initial stack, `stack_top` is where `rsp` starts. The page below `stack_bottom` is left
+    /// unmapped as a guard page, so overflowing the stack page faults instead of overwriting other memory.
+    pub stack_bottom: u64,
+    pub stack_top: u64,
+
+    /// The slot the kernel was booted from
+    pub boot_slot: BootSlot,
+    /// Set when `boot_slot` is a pending update that hasn't been confirmed yet, see [`BootState`]
+    pub trial_boot: bool,
+
+    /// The framebuffer's `base` is a physical address
+    pub framebuffer: Framebuffer,
+
+    /// The memory map as it was when boot services were exited
+    pub memory_map: *const MemoryRegion,
+    pub memory_map_len: usize,
+
+    /// Physical address of the ACPI RSDP, or 0 if the firmware didn't provide one
+    pub acpi_rsdp: u64,
+
+    /// Virtual address of the UEFI runtime services table, or 0 if they aren't available. The runtime services have
+    /// been switched to virtual mode, with every runtime region at `hhdm_offset` plus its physical address.
+    pub efi_runtime_services: u64,
+
+    /// UTF-8 kernel command line, not null terminated
+    pub cmdline: *const u8,
+    pub cmdline_len: usize,
+
+    /// Files loaded alongside the kernel, the initrd (if any) is named `initrd`
+    pub modules: *const BootModule,
+    pub modules_len: usize,
+
+    /// Every processor in the system in the firmware's order, empty if the firmware has no MP services
+    pub processors: *const ProcessorInfo,
+    pub processors_len: usize,
+
+    pub timestamps: LoaderTimestamps,
+}
+
+impl UEFIBootInfo {
+    /// Creates an empty boot info with a valid header, to be filled in by the loader
+    pub const fn new() -> Self {
+        Self {
+            header: BootInfoHeader {
+                magic: BOOT_INFO_MAGIC,
+                version: BOOT_INFO_VERSION,
+                size: size_of::<Self>() as u32,
+            },
+
+            hhdm_offset: 0,
+
+            kernel_slide: 0,
+
+            stack_bottom: 0,
+            stack_top: 0,
+
+            boot_slot: BootSlot::None,
+            trial_boot: false,
+
+            framebuffer: Framebuffer::empty(),
+
+            memory_map: core::ptr::null(),
+            memory_map_len: 0,
+
+            acpi_rsdp: 0,
+
+            efi_runtime_services: 0,
+
+            cmdline: core::ptr::null(),
+            cmdline_len: 0,
+
+            modules: core::ptr::null(),
+            modules_len: 0,
+
+            processors: core::ptr::null(),
+            processors_len: 0,
+
+            timestamps: LoaderTimestamps::empty(),
+        }
+    }
+
+    /// Validates the header behind `ptr` and copies the boot info out of loader memory.
+    ///
+    /// Only the header is read until it has been validated, so a pointer to something that isn't a
+    /// boot info (or one from a loader built against a different layout) is rejected instead of
+    /// being read as garbage.
+    ///
+    /// # Safety
+    /// `ptr` must either be null or point to at least `size_of::<BootInfoHeader>()` readable bytes.
+    pub unsafe fn read_from(ptr: *const UEFIBootInfo) -> Result<UEFIBootInfo, BootInfoError> {
+        if ptr.is_null() {
+            return Err(BootInfoError::NullPointer);
+        }
+
+        // SAFETY: the caller guarantees that at least a header is readable
+        let header = unsafe { ptr.cast::<BootInfoHeader>().read_unaligned() };
+
+        if header.magic != BOOT_INFO_MAGIC {
+            return Err(BootInfoError::BadMagic(header.magic));
+        }
+
+        if header.version != BOOT_INFO_VERSION {
+            return Err(BootInfoError::VersionMismatch {
+                expected: BOOT_INFO_VERSION,
+                found: header.version,
+            });
+        }
+
+        if (header.size as usize) < size_of::<Self>() {
+            return Err(BootInfoError::Truncated {
+                expected: size_of::<Self>() as u32,
+                found: header.size,
+            });
+        }
+
+        // SAFETY: the header says the loader wrote a full boot info of our version
+        Ok(unsafe { ptr.read_unaligned() })
+    }
+
+    pub fn header(&self) -> &BootInfoHeader {
+        &self.header
+    }
+
+    /// # Safety
+    /// The memory map written by the loader must still be mapped at the address it was passed at.
+    pub unsafe fn memory_map(&self) -> &[MemoryRegion] {
+        if self.memory_map.is_null() {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.memory_map, self.memory_map_len) }
+    }
+
+    /// The kernel command line, or an empty string if none was passed (or it isn't valid UTF-8)
+    ///
+    /// # Safety
+    /// The command line written by the loader must still be mapped at the address it was passed at.
+    pub unsafe fn cmdline(&self) -> &str {
+        if self.cmdline.is_null() {
+            return "";
+        }
+
+        let bytes = unsafe { core::slice::from_raw_parts(self.cmdline, self.cmdline_len) };
+        core::str::from_utf8(bytes).unwrap_or("")
+    }
+
+    /// # Safety
+    /// The module list written by the loader must still be mapped at the address it was passed at.
+    pub unsafe fn modules(&self) -> &[BootModule] {
+        if self.modules.is_null() {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.modules, self.modules_len) }
+    }
+
+    /// # Safety
+    /// The processor list written by the loader must still be mapped at the address it was passed at.
+    pub unsafe fn processors(&self) -> &[ProcessorInfo] {
+        if self.processors.is_null() {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.processors, self.processors_len) }
+    }
+}
+
+impl Default for UEFIBootInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
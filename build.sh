@@ -1,3 +1,10 @@
+# With an Ed25519 key in signing_key.pem (openssl genpkey -algorithm ed25519 -out signing_key.pem) its public key is
+# built into the loader and the kernel gets signed, otherwise the kernel only gets a SHA-256 digest
+if [ -f signing_key.pem ]; then
+  GROVE_KERNEL_PUBKEY=$(openssl pkey -in signing_key.pem -pubout -outform DER | tail -c 32 | xxd -p -c 32) || exit
+  export GROVE_KERNEL_PUBKEY
+fi
+
 pushd uefi_loader
 cargo build --target x86_64-unknown-uefi || exit
 popd
@@ -10,4 +17,14 @@ mkdir -p esp/efi/boot
 cp target/x86_64-unknown-uefi/debug/uefi_loader.efi ./esp/efi/boot/bootx64.efi
 cp target/x86_64-unknown-groveos/debug/kernel ./esp/kernel.elf
 
+(cd esp && sha256sum kernel.elf > kernel.elf.sha256) || exit
+# The loader prefers the compressed kernel, which is a lot less to read from the ESP. Its content size has to be in
+# the frame header so the loader can allocate the kernel's buffer up front.
+lz4 -q -f -9 --content-size esp/kernel.elf esp/kernel.elf.lz4 || exit
+if [ -f signing_key.pem ]; then
+  openssl pkeyutl -sign -rawin -inkey signing_key.pem -in esp/kernel.elf -out esp/kernel.elf.sig || exit
+else
+  rm -f esp/kernel.elf.sig
+fi
+
 qemu-system-x86_64 -drive if=pflash,format=raw,readonly=on,file=OVMF_CODE.fd -drive if=pflash,format=raw,readonly=on,file=OVMF_VARS.fd -drive format=raw,file=fat:rw:esp -d int,cpu_reset -D qemu.log
\ No newline at end of file
@@ -0,0 +1,157 @@
+use crate::acpi::{AcpiTable, GenericAddress, SdtHeader};
+use core::mem::offset_of;
+
+/// Fixed ACPI Description Table, describes the fixed hardware registers used for power management.
+///
+/// Older firmware passes shorter versions of this table, so fields past the 1.0 layout should be read through the
+/// accessors below, which check the table length first.
+#[repr(C, packed)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    reserved0: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_request: u8,
+    pub pstate_control: u8,
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm2_control_block: u32,
+    pub pm_timer_block: u32,
+    pub gpe0_block: u32,
+    pub gpe1_block: u32,
+    pub pm1_event_length: u8,
+    pub pm1_control_length: u8,
+    pub pm2_control_length: u8,
+    pub pm_timer_length: u8,
+    pub gpe0_block_length: u8,
+    pub gpe1_block_length: u8,
+    pub gpe1_base: u8,
+    pub cstate_control: u8,
+    pub c2_latency: u16,
+    pub c3_latency: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alarm: u8,
+    pub month_alarm: u8,
+    century: u8,
+    iapc_boot_arch: u16,
+    reserved1: u8,
+    flags: u32,
+    reset_register: GenericAddress,
+    reset_value: u8,
+    arm_boot_arch: u16,
+    minor_version: u8,
+    x_firmware_ctrl: u64,
+    x_dsdt: u64,
+    x_pm1a_event_block: GenericAddress,
+    x_pm1b_event_block: GenericAddress,
+    x_pm1a_control_block: GenericAddress,
+    x_pm1b_control_block: GenericAddress,
+    x_pm2_control_block: GenericAddress,
+    x_pm_timer_block: GenericAddress,
+    x_gpe0_block: GenericAddress,
+    x_gpe1_block: GenericAddress,
+}
+
+impl AcpiTable for Fadt {
+    const SIGNATURE: [u8; 4] = *b"FACP";
+    /// Everything up to the public fields, the rest is read through the accessors
+    const MIN_LENGTH: usize = offset_of!(Fadt, century);
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+impl Fadt {
+    pub const FLAG_RESET_REG_SUPPORTED: u32 = 1 << 10;
+    pub const FLAG_HW_REDUCED_ACPI: u32 = 1 << 20;
+
+    pub const BOOT_ARCH_LEGACY_DEVICES: u16 = 1 << 0;
+    pub const BOOT_ARCH_8042: u16 = 1 << 1;
+    pub const BOOT_ARCH_NO_VGA: u16 = 1 << 2;
+    pub const BOOT_ARCH_NO_CMOS_RTC: u16 = 1 << 5;
+
+    fn has_field(&self, end_offset: usize) -> bool {
+        self.header.length as usize >= end_offset
+    }
+
+    /// The CMOS RAM index of the RTC century register, if the firmware reports one
+    pub fn century_register(&self) -> Option<u8> {
+        if !self.has_field(offset_of!(Fadt, century) + 1) {
+            return None;
+        }
+
+        match self.century {
+            0 => None,
+            index => Some(index),
+        }
+    }
+
+    /// IA-PC boot architecture flags (`BOOT_ARCH_*`), zero on ACPI 1.0 tables
+    pub fn iapc_boot_arch(&self) -> u16 {
+        if self.has_field(offset_of!(Fadt, iapc_boot_arch) + 2) {
+            self.iapc_boot_arch
+        } else {
+            0
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        if self.has_field(offset_of!(Fadt, flags) + 4) {
+            self.flags
+        } else {
+            0
+        }
+    }
+
+    /// The register and value to write to it to reset the system
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        if self.flags() & Self::FLAG_RESET_REG_SUPPORTED == 0
+            || !self.has_field(offset_of!(Fadt, reset_value) + 1)
+        {
+            return None;
+        }
+
+        Some((self.reset_register, self.reset_value))
+    }
+
+    /// Physical address of the DSDT, preferring the 64-bit field when present
+    pub fn dsdt_address(&self) -> u64 {
+        if self.has_field(offset_of!(Fadt, x_dsdt) + 8) && self.x_dsdt != 0 {
+            self.x_dsdt
+        } else {
+            self.dsdt as u64
+        }
+    }
+
+    /// The ACPI PM timer block, preferring the extended address when present
+    pub fn pm_timer(&self) -> Option<GenericAddress> {
+        if self.has_field(offset_of!(Fadt, x_pm_timer_block) + size_of::<GenericAddress>()) {
+            let block = self.x_pm_timer_block;
+            if { block.address } != 0 {
+                return Some(block);
+            }
+        }
+
+        match self.pm_timer_block {
+            0 => None,
+            port => Some(GenericAddress {
+                address_space: GenericAddress::SYSTEM_IO,
+                bit_width: 32,
+                bit_offset: 0,
+                access_size: 3,
+                address: port as u64,
+            }),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use crate::acpi::{AcpiTable, GenericAddress, SdtHeader};
+
+/// High Precision Event Timer description table
+#[repr(C, packed)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl AcpiTable for Hpet {
+    const SIGNATURE: [u8; 4] = *b"HPET";
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+impl Hpet {
+    /// Physical address of the HPET register block
+    pub fn base_address(&self) -> u64 {
+        self.base_address.address
+    }
+
+    pub fn comparator_count(&self) -> u8 {
+        (((self.event_timer_block_id >> 8) & 0x1F) + 1) as u8
+    }
+
+    pub fn pci_vendor_id(&self) -> u16 {
+        (self.event_timer_block_id >> 16) as u16
+    }
+}
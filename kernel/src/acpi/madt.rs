@@ -0,0 +1,208 @@
+use crate::acpi::{AcpiTable, SdtHeader};
+
+/// Multiple APIC Description Table, lists the interrupt controllers in the system
+#[repr(C, packed)]
+pub struct Madt {
+    pub header: SdtHeader,
+    pub local_apic_address: u32,
+    pub flags: u32,
+}
+
+impl AcpiTable for Madt {
+    const SIGNATURE: [u8; 4] = *b"APIC";
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+impl Madt {
+    const PCAT_COMPAT: u32 = 1 << 0;
+
+    /// Whether the system also has dual 8259 PICs that need to be masked before using the APICs
+    pub fn has_legacy_pics(&self) -> bool {
+        self.flags & Self::PCAT_COMPAT != 0
+    }
+
+    pub fn entries(&self) -> MadtIter {
+        MadtIter {
+            bytes: self
+                .header
+                .body()
+                .get(size_of::<Madt>() - size_of::<SdtHeader>()..)
+                .unwrap_or(&[]),
+        }
+    }
+
+    /// The physical address of the local APIC, taking a 64-bit address override into account
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .unwrap_or(self.local_apic_address as u64)
+    }
+}
+
+/// Polarity and trigger mode flags shared by several MADT entries
+#[derive(Copy, Clone, Debug)]
+pub struct MpsIntiFlags(pub u16);
+
+impl MpsIntiFlags {
+    /// `None` means the polarity conforms to the bus specification
+    pub fn active_low(&self) -> Option<bool> {
+        match self.0 & 0b11 {
+            0b01 => Some(false),
+            0b11 => Some(true),
+            _ => None,
+        }
+    }
+
+    /// `None` means the trigger mode conforms to the bus specification
+    pub fn level_triggered(&self) -> Option<bool> {
+        match (self.0 >> 2) & 0b11 {
+            0b01 => Some(false),
+            0b11 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_uid: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    InterruptSourceOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: MpsIntiFlags,
+    },
+    NmiSource {
+        flags: MpsIntiFlags,
+        gsi: u32,
+    },
+    LocalApicNmi {
+        processor_uid: u8,
+        flags: MpsIntiFlags,
+        lint: u8,
+    },
+    LocalApicAddressOverride {
+        address: u64,
+    },
+    LocalX2Apic {
+        x2apic_id: u32,
+        flags: u32,
+        processor_uid: u32,
+    },
+    LocalX2ApicNmi {
+        flags: MpsIntiFlags,
+        processor_uid: u32,
+        lint: u8,
+    },
+    Unknown {
+        entry_type: u8,
+    },
+}
+
+impl MadtEntry {
+    /// Local APIC flag marking the processor as usable
+    pub const PROCESSOR_ENABLED: u32 = 1 << 0;
+    /// Local APIC flag marking a disabled processor that can be brought online later
+    pub const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+    fn parse(entry_type: u8, data: &[u8]) -> Option<Self> {
+        let u8_at = |offset: usize| data.get(offset).copied();
+        let u16_at = |offset: usize| {
+            Some(u16::from_le_bytes(
+                data.get(offset..offset + 2)?.try_into().ok()?,
+            ))
+        };
+        let u32_at = |offset: usize| {
+            Some(u32::from_le_bytes(
+                data.get(offset..offset + 4)?.try_into().ok()?,
+            ))
+        };
+        let u64_at = |offset: usize| {
+            Some(u64::from_le_bytes(
+                data.get(offset..offset + 8)?.try_into().ok()?,
+            ))
+        };
+
+        // Offsets are relative to the end of the 2 byte type/length header
+        Some(match entry_type {
+            0 => MadtEntry::LocalApic {
+                processor_uid: u8_at(0)?,
+                apic_id: u8_at(1)?,
+                flags: u32_at(2)?,
+            },
+            1 => MadtEntry::IoApic {
+                id: u8_at(0)?,
+                address: u32_at(2)?,
+                gsi_base: u32_at(6)?,
+            },
+            2 => MadtEntry::InterruptSourceOverride {
+                bus: u8_at(0)?,
+                source: u8_at(1)?,
+                gsi: u32_at(2)?,
+                flags: MpsIntiFlags(u16_at(6)?),
+            },
+            3 => MadtEntry::NmiSource {
+                flags: MpsIntiFlags(u16_at(0)?),
+                gsi: u32_at(2)?,
+            },
+            4 => MadtEntry::LocalApicNmi {
+                processor_uid: u8_at(0)?,
+                flags: MpsIntiFlags(u16_at(1)?),
+                lint: u8_at(3)?,
+            },
+            5 => MadtEntry::LocalApicAddressOverride {
+                address: u64_at(2)?,
+            },
+            9 => MadtEntry::LocalX2Apic {
+                x2apic_id: u32_at(2)?,
+                flags: u32_at(6)?,
+                processor_uid: u32_at(10)?,
+            },
+            10 => MadtEntry::LocalX2ApicNmi {
+                flags: MpsIntiFlags(u16_at(0)?),
+                processor_uid: u32_at(2)?,
+                lint: u8_at(6)?,
+            },
+            entry_type => MadtEntry::Unknown { entry_type },
+        })
+    }
+}
+
+pub struct MadtIter {
+    bytes: &'static [u8],
+}
+
+impl Iterator for MadtIter {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry_type = *self.bytes.first()?;
+        let len = *self.bytes.get(1)? as usize;
+
+        // A length under 2 would never advance, so treat it as the end of the table
+        if len < 2 || len > self.bytes.len() {
+            self.bytes = &[];
+            return None;
+        }
+
+        let data = &self.bytes[2..len];
+        self.bytes = &self.bytes[len..];
+
+        MadtEntry::parse(entry_type, data).or(Some(MadtEntry::Unknown { entry_type }))
+    }
+}
@@ -0,0 +1,59 @@
+use crate::acpi::{AcpiTable, SdtHeader};
+
+/// PCI Express memory mapped configuration space table
+#[repr(C, packed)]
+pub struct Mcfg {
+    pub header: SdtHeader,
+    reserved: u64,
+}
+
+impl AcpiTable for Mcfg {
+    const SIGNATURE: [u8; 4] = *b"MCFG";
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+/// One ECAM region, covering the buses `start_bus..=end_bus` of a PCI segment group
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    reserved: u32,
+}
+
+impl Mcfg {
+    pub fn entries(&self) -> &'static [McfgEntry] {
+        let body = self.header.body().get(size_of::<u64>()..).unwrap_or(&[]);
+
+        // SAFETY: McfgEntry is packed, so any byte address is suitably aligned for it
+        unsafe {
+            core::slice::from_raw_parts(
+                body.as_ptr() as *const McfgEntry,
+                body.len() / size_of::<McfgEntry>(),
+            )
+        }
+    }
+}
+
+impl McfgEntry {
+    /// Physical address of the configuration space of a function, if its bus is covered by this entry and `device`
+    /// and `function` fit in their 5 and 3 bits. The base address is where bus 0 would be, even if the entry starts
+    /// at a later bus.
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> Option<u64> {
+        if bus < self.start_bus || bus > self.end_bus || device >= 32 || function >= 8 {
+            return None;
+        }
+
+        Some(
+            self.base_address
+                + ((bus as u64) << 20)
+                + ((device as u64) << 15)
+                + ((function as u64) << 12),
+        )
+    }
+}
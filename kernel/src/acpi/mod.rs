@@ -0,0 +1,276 @@
+use crate::acpi::fadt::Fadt;
+use crate::acpi::hpet::Hpet;
+use crate::acpi::madt::{Madt, MadtEntry};
+use crate::acpi::mcfg::Mcfg;
+use crate::mem::page::PhysAddr;
+use crate::mem::phys_to_virt;
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+#[derive(Debug)]
+pub enum AcpiError {
+    NoRsdp,
+    BadRsdpSignature,
+    BadRsdpChecksum,
+    BadRootTable,
+}
+
+/// Implemented by every typed table that can be looked up with [`find_table`]
+pub trait AcpiTable: Sized {
+    const SIGNATURE: [u8; 4];
+    /// Shortest table [`find_table`] accepts, tables that grew over ACPI revisions lower this to their oldest layout
+    const MIN_LENGTH: usize = size_of::<Self>();
+
+    fn header(&self) -> &SdtHeader;
+}
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+
+    // ACPI 2.0+ fields
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+impl Rsdp {
+    const SIGNATURE: [u8; 8] = *b"RSD PTR ";
+    const V1_LENGTH: usize = 20;
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    pub fn signature(&self) -> &str {
+        str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    pub fn oem_id(&self) -> &str {
+        str::from_utf8(&self.oem_id).unwrap_or("??????")
+    }
+
+    /// The whole table, header included
+    pub fn bytes(&self) -> &'static [u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8, self.length as usize)
+        }
+    }
+
+    /// The part of the table following the header
+    pub fn body(&self) -> &'static [u8] {
+        &self.bytes()[size_of::<SdtHeader>()..]
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.length as usize >= size_of::<SdtHeader>() && checksum(self.bytes())
+    }
+}
+
+/// Generic Address Structure, used by ACPI to describe registers in any address space
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+}
+
+/// The XSDT (or the RSDT on ACPI 1.0 systems), which holds the addresses of every other table
+struct RootTable {
+    header: &'static SdtHeader,
+    entry_size: usize,
+}
+
+impl RootTable {
+    fn tables(&self) -> impl Iterator<Item = &'static SdtHeader> {
+        let entry_size = self.entry_size;
+
+        self.header
+            .body()
+            .chunks_exact(entry_size)
+            .map(move |entry| {
+                let addr = if entry_size == 8 {
+                    u64::from_le_bytes(entry.try_into().expect("entry is 8 bytes"))
+                } else {
+                    u32::from_le_bytes(entry.try_into().expect("entry is 4 bytes")) as u64
+                };
+
+                phys_ref::<SdtHeader>(addr)
+            })
+    }
+}
+
+static mut ROOT_TABLE: Option<RootTable> = None;
+
+pub fn init(rsdp: PhysAddr) -> Result<(), AcpiError> {
+    if rsdp == 0 {
+        return Err(AcpiError::NoRsdp);
+    }
+
+    let rsdp_ref = phys_ref::<Rsdp>(rsdp);
+    if rsdp_ref.signature != Rsdp::SIGNATURE {
+        return Err(AcpiError::BadRsdpSignature);
+    }
+
+    let rsdp_bytes =
+        |len: usize| unsafe { core::slice::from_raw_parts(phys_to_virt(rsdp) as *const u8, len) };
+    if !checksum(rsdp_bytes(Rsdp::V1_LENGTH)) {
+        return Err(AcpiError::BadRsdpChecksum);
+    }
+
+    let root = if rsdp_ref.revision >= 2 {
+        if !checksum(rsdp_bytes(rsdp_ref.length as usize)) {
+            return Err(AcpiError::BadRsdpChecksum);
+        }
+
+        RootTable {
+            header: phys_ref(rsdp_ref.xsdt_address),
+            entry_size: size_of::<u64>(),
+        }
+    } else {
+        RootTable {
+            header: phys_ref(rsdp_ref.rsdt_address as PhysAddr),
+            entry_size: size_of::<u32>(),
+        }
+    };
+
+    if !root.header.is_valid() {
+        return Err(AcpiError::BadRootTable);
+    }
+
+    unsafe {
+        ROOT_TABLE = Some(root);
+    }
+
+    Ok(())
+}
+
+/// Every table listed in the XSDT/RSDT, including ones with invalid checksums
+pub fn tables() -> impl Iterator<Item = &'static SdtHeader> {
+    #[allow(static_mut_refs)]
+    unsafe { ROOT_TABLE.as_ref() }
+        .into_iter()
+        .flat_map(|root| root.tables())
+}
+
+pub fn find_table<T: AcpiTable>() -> Option<&'static T> {
+    tables()
+        .find(|table| {
+            table.signature == T::SIGNATURE
+                && table.length as usize >= T::MIN_LENGTH
+                && table.is_valid()
+        })
+        .map(|table| unsafe { &*(table as *const SdtHeader as *const T) })
+}
+
+pub fn madt() -> Option<&'static Madt> {
+    find_table()
+}
+
+pub fn fadt() -> Option<&'static Fadt> {
+    find_table()
+}
+
+pub fn hpet() -> Option<&'static Hpet> {
+    find_table()
+}
+
+pub fn mcfg() -> Option<&'static Mcfg> {
+    find_table()
+}
+
+pub fn print_acpi_info() {
+    crate::println!("---------- ACPI Tables ----------");
+    for table in tables() {
+        crate::println!(
+            "{} @ {:#x} ({} bytes, OEM {}){}",
+            table.signature(),
+            table as *const SdtHeader as u64,
+            { table.length },
+            table.oem_id(),
+            if table.is_valid() {
+                ""
+            } else {
+                " [bad checksum]"
+            }
+        );
+    }
+
+    if let Some(madt) = madt() {
+        let cpus = madt
+            .entries()
+            .filter(|entry| {
+                matches!(
+                    entry,
+                    MadtEntry::LocalApic { .. } | MadtEntry::LocalX2Apic { .. }
+                )
+            })
+            .count();
+        let io_apics = madt
+            .entries()
+            .filter(|entry| matches!(entry, MadtEntry::IoApic { .. }))
+            .count();
+
+        crate::println!(
+            "MADT: LAPIC @ {:#x}, {} processors, {} IOAPICs",
+            madt.local_apic_address(),
+            cpus,
+            io_apics
+        );
+    }
+    if let Some(hpet) = hpet() {
+        crate::println!("HPET @ {:#x}", hpet.base_address());
+    }
+    if let Some(mcfg) = mcfg() {
+        for entry in mcfg.entries() {
+            crate::println!(
+                "PCIe segment {} buses {}-{} @ {:#x}",
+                { entry.segment_group },
+                entry.start_bus,
+                entry.end_bus,
+                { entry.base_address }
+            );
+        }
+    }
+    if let Some(fadt) = fadt() {
+        crate::println!("FADT: SCI IRQ {}", { fadt.sci_interrupt });
+    }
+    crate::println!("---------------------------------");
+}
+
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// ACPI tables are read in place through the direct map
+fn phys_ref<T>(addr: PhysAddr) -> &'static T {
+    unsafe { &*(phys_to_virt(addr) as *const T) }
+}
@@ -0,0 +1,71 @@
+//! Files the loader put in memory for the kernel, such as the initrd.
+
+use crate::mem::phys_to_virt;
+use crate::println;
+use boot_protocol::{BootModule, UEFIBootInfo};
+
+const MAX_BOOT_MODULES: usize = 16;
+
+static mut MODULES: [BootModule; MAX_BOOT_MODULES] = [BootModule::empty(); MAX_BOOT_MODULES];
+static mut MODULES_LEN: usize = 0;
+/// Modules past [`MAX_BOOT_MODULES`] that didn't fit
+static mut MODULES_SKIPPED: usize = 0;
+
+/// Copies the module list out of loader memory. The module contents stay where the loader put them.
+pub fn init(boot_info: &UEFIBootInfo) {
+    // SAFETY: this runs before the kernel page table is installed, so the loader's identity mapping is still active
+    let modules = unsafe { boot_info.modules() };
+
+    let len = modules.len().min(MAX_BOOT_MODULES);
+
+    #[allow(static_mut_refs)]
+    unsafe {
+        MODULES[..len].copy_from_slice(&modules[..len]);
+        MODULES_LEN = len;
+        MODULES_SKIPPED = modules.len() - len;
+    }
+}
+
+pub fn modules() -> &'static [BootModule] {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &MODULES[..MODULES_LEN]
+    }
+}
+
+pub fn find(name: &str) -> Option<&'static BootModule> {
+    modules().iter().find(|module| module.name() == name)
+}
+
+pub fn initrd() -> Option<&'static BootModule> {
+    find("initrd")
+}
+
+/// The contents of `module`, read in place through the direct map
+pub fn data(module: &BootModule) -> &'static [u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            phys_to_virt(module.phys_addr) as *const u8,
+            module.size as usize,
+        )
+    }
+}
+
+pub fn print_modules() {
+    for module in modules() {
+        println!(
+            "Boot module '{}' @ {:#x} ({} bytes)",
+            module.name(),
+            module.phys_addr,
+            module.size
+        );
+    }
+
+    let skipped = unsafe { MODULES_SKIPPED };
+    if skipped > 0 {
+        println!(
+            "Ignoring {} boot modules, at most {} are supported",
+            skipped, MAX_BOOT_MODULES
+        );
+    }
+}
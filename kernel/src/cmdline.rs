@@ -0,0 +1,258 @@
+//! Kernel command line parameters.
+//!
+//! The command line is a whitespace separated list of `key=value` pairs and bare flags (e.g. `loglevel=debug nosmp`).
+//! Subsystems declare the parameters they understand as [`Param`]s or [`Flag`]s and query them whenever they need
+//! to, which is possible as soon as [`init`] has run at the very start of `_start`. When a key is given more than
+//! once, the last occurrence wins.
+
+use crate::println;
+use boot_protocol::UEFIBootInfo;
+
+const MAX_CMDLINE_LEN: usize = 1024;
+
+static mut CMDLINE: [u8; MAX_CMDLINE_LEN] = [0; MAX_CMDLINE_LEN];
+static mut CMDLINE_LEN: usize = 0;
+/// Length of the command line the loader passed, which is more than [`CMDLINE_LEN`] if it had to be cut off
+static mut ORIGINAL_LEN: usize = 0;
+
+pub static LOGLEVEL: Param<LogLevel> = Param::new("loglevel", LogLevel::Info);
+pub static SERIAL: Param<Option<SerialPort>> = Param::new("serial", None);
+pub static INIT: Param<&'static str> = Param::new("init", "/bin/init");
+pub static NOSMP: Flag = Flag::new("nosmp");
+/// Read by the loader, which then loads the kernel at its link address
+pub static NOKASLR: Flag = Flag::new("nokaslr");
+
+/// Every parameter the kernel knows about, anything else on the command line gets reported as unknown
+static KNOWN_PARAMS: &[&(dyn KnownParam + Sync)] = &[&LOGLEVEL, &SERIAL, &INIT, &NOSMP, &NOKASLR];
+
+/// Copies the command line out of loader memory, so it stays available after the loader's mappings are gone. Anything
+/// past [`MAX_CMDLINE_LEN`] bytes is dropped, [`print_cmdline`] warns about that since nothing can be printed yet.
+pub fn init(boot_info: &UEFIBootInfo) {
+    // SAFETY: this runs before the kernel page table is installed, so the loader's identity mapping is still active
+    let cmdline = unsafe { boot_info.cmdline() };
+
+    let mut len = cmdline.len().min(MAX_CMDLINE_LEN);
+    while !cmdline.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    #[allow(static_mut_refs)]
+    unsafe {
+        CMDLINE[..len].copy_from_slice(&cmdline.as_bytes()[..len]);
+        CMDLINE_LEN = len;
+        ORIGINAL_LEN = cmdline.len();
+    }
+}
+
+pub fn cmdline() -> &'static str {
+    #[allow(static_mut_refs)]
+    unsafe {
+        str::from_utf8_unchecked(&CMDLINE[..CMDLINE_LEN])
+    }
+}
+
+/// All `(key, value)` pairs in the order they were given, bare flags have no value
+pub fn params() -> impl Iterator<Item = (&'static str, Option<&'static str>)> {
+    cmdline()
+        .split_whitespace()
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (param, None),
+        })
+}
+
+/// The raw value of `key`, `Some("")` if it was passed as a bare flag
+pub fn get(key: &str) -> Option<&'static str> {
+    params()
+        .filter(|(name, _)| *name == key)
+        .last()
+        .map(|(_, value)| value.unwrap_or(""))
+}
+
+pub fn print_cmdline() {
+    println!("Command line: {}", cmdline());
+
+    let original_len = unsafe { ORIGINAL_LEN };
+    if original_len > cmdline().len() {
+        println!(
+            "Command line is {} bytes long, everything past the first {} was ignored",
+            original_len,
+            cmdline().len()
+        );
+    }
+
+    for (key, value) in params() {
+        match KNOWN_PARAMS.iter().find(|param| param.name() == key) {
+            None => println!("Ignoring unknown kernel parameter '{}'", key),
+            Some(param) if !param.accepts(value.unwrap_or("")) => println!(
+                "Ignoring invalid value '{}' for kernel parameter '{}'",
+                value.unwrap_or(""),
+                key
+            ),
+            Some(_) => {}
+        }
+    }
+}
+
+trait KnownParam {
+    fn name(&self) -> &'static str;
+
+    fn accepts(&self, value: &'static str) -> bool;
+}
+
+/// A value that can be read from the command line
+pub trait ParamValue: Sized {
+    fn parse(value: &'static str) -> Option<Self>;
+}
+
+/// A typed `key=value` parameter, falling back to `default` when missing or invalid
+pub struct Param<T: 'static> {
+    name: &'static str,
+    default: T,
+}
+
+impl<T: ParamValue + Copy> Param<T> {
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Self { name, default }
+    }
+
+    pub fn get(&self) -> T {
+        self.try_get().unwrap_or(self.default)
+    }
+
+    /// The value passed on the command line, `None` if it was missing or invalid
+    pub fn try_get(&self) -> Option<T> {
+        get(self.name).and_then(T::parse)
+    }
+}
+
+impl<T: ParamValue> KnownParam for Param<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn accepts(&self, value: &'static str) -> bool {
+        T::parse(value).is_some()
+    }
+}
+
+/// A bare flag such as `nosmp`, which is set by being present (or by `key=1`/`key=true`/`key=on`)
+pub struct Flag {
+    name: &'static str,
+}
+
+impl Flag {
+    pub const fn new(name: &'static str) -> Self {
+        Self { name }
+    }
+
+    pub fn is_set(&self) -> bool {
+        get(self.name).is_some_and(|value| value.is_empty() || bool::parse(value) == Some(true))
+    }
+}
+
+impl KnownParam for Flag {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn accepts(&self, value: &'static str) -> bool {
+        value.is_empty() || bool::parse(value).is_some()
+    }
+}
+
+impl ParamValue for &'static str {
+    fn parse(value: &'static str) -> Option<Self> {
+        (!value.is_empty()).then_some(value)
+    }
+}
+
+impl ParamValue for bool {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "1" | "true" | "on" | "yes" => Some(true),
+            "0" | "false" | "off" | "no" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! int_param_value {
+    ($($ty:ty),*) => {
+        $(
+            impl ParamValue for $ty {
+                fn parse(value: &'static str) -> Option<Self> {
+                    match value.strip_prefix("0x") {
+                        Some(hex) => <$ty>::from_str_radix(hex, 16).ok(),
+                        None => value.parse().ok(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+int_param_value!(u8, u16, u32, u64, usize);
+
+/// Optional parameters can be explicitly cleared with `key=none` or `key=off`
+impl<T: ParamValue> ParamValue for Option<T> {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "none" | "off" => Some(None),
+            value => T::parse(value).map(Some),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl ParamValue for LogLevel {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SerialPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl SerialPort {
+    pub fn io_port(&self) -> u16 {
+        match self {
+            SerialPort::Com1 => 0x3F8,
+            SerialPort::Com2 => 0x2F8,
+            SerialPort::Com3 => 0x3E8,
+            SerialPort::Com4 => 0x2E8,
+        }
+    }
+}
+
+impl ParamValue for SerialPort {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "com1" => Some(SerialPort::Com1),
+            "com2" => Some(SerialPort::Com2),
+            "com3" => Some(SerialPort::Com3),
+            "com4" => Some(SerialPort::Com4),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! The local APIC of the bootstrap processor, in x2APIC mode when the CPU supports it.
+//!
+//! The legacy 8259 PICs are remapped away from the exception vectors and masked by [`init`], so after it has run
+//! the only interrupts that can come in are the ones programmed through the APICs.
+
+use crate::cpu::idt::{self, IDTEntry};
+use crate::cpu::{cpuid, inb, outb, rdmsr, wrmsr};
+use crate::mem::page::VirtAddr;
+use crate::mem::phys_to_virt;
+use core::arch::naked_asm;
+
+/// Vector of the spurious interrupts the local APIC raises, which don't get an EOI
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Where the 8259 PICs' IRQs end up if one slips through before they're masked
+const PIC_VECTOR_BASE: u8 = 0x20;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// The x2APIC MSRs start here, register `offset` is MSR `X2APIC_MSR_BASE + offset / 16`
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+pub const REG_ID: u32 = 0x20;
+pub const REG_VERSION: u32 = 0x30;
+pub const REG_TPR: u32 = 0x80;
+pub const REG_EOI: u32 = 0xB0;
+pub const REG_SPURIOUS: u32 = 0xF0;
+pub const REG_ESR: u32 = 0x280;
+pub const REG_ICR_LOW: u32 = 0x300;
+pub const REG_ICR_HIGH: u32 = 0x310;
+pub const REG_LVT_TIMER: u32 = 0x320;
+pub const REG_LVT_ERROR: u32 = 0x370;
+pub const REG_TIMER_INITIAL_COUNT: u32 = 0x380;
+pub const REG_TIMER_CURRENT_COUNT: u32 = 0x390;
+pub const REG_TIMER_DIVIDE: u32 = 0x3E0;
+
+/// Set in an LVT entry to keep it from raising interrupts
+pub const LVT_MASKED: u32 = 1 << 16;
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+static mut X2APIC: bool = false;
+/// Direct map address of the xAPIC registers, unused in x2APIC mode
+static mut BASE: VirtAddr = 0;
+
+/// An inter-processor interrupt
+#[derive(Copy, Clone, Debug)]
+pub enum Ipi {
+    /// Raises the vector on the target
+    Fixed(u8),
+    Nmi,
+    /// Resets the target, the first step in starting an AP
+    Init,
+    /// Starts the target in real mode at the page with this number, sent after [`Ipi::Init`]
+    Startup(u8),
+}
+
+impl Ipi {
+    fn command(self) -> u32 {
+        match self {
+            Ipi::Fixed(vector) => vector as u32,
+            Ipi::Nmi => 0b100 << 8,
+            Ipi::Init => 0b101 << 8 | ICR_LEVEL_ASSERT,
+            Ipi::Startup(page) => 0b110 << 8 | page as u32 | ICR_LEVEL_ASSERT,
+        }
+    }
+}
+
+/// Shuts the 8259 PICs up and enables the local APIC, after which interrupts can be enabled
+pub fn init() {
+    disable_legacy_pics();
+
+    let x2apic = supports_x2apic();
+    let base = unsafe { rdmsr(IA32_APIC_BASE) };
+
+    // x2APIC mode can only be entered from an enabled xAPIC, so the enable bit goes first
+    unsafe {
+        wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE);
+        if x2apic {
+            wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE | APIC_BASE_X2APIC);
+        }
+
+        X2APIC = x2apic;
+        BASE = phys_to_virt(base & APIC_BASE_ADDRESS_MASK);
+    }
+
+    idt::set_idt_entry(
+        IDTEntry::new(spurious_interrupt as *const () as u64, None),
+        SPURIOUS_VECTOR as usize,
+    );
+
+    // Nothing is routed to the timer or error LVTs until someone sets them up
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_LVT_ERROR, LVT_MASKED);
+    write(REG_TPR, 0);
+    write(REG_SPURIOUS, SPURIOUS_APIC_ENABLE | SPURIOUS_VECTOR as u32);
+
+    // Clear anything left over from the firmware
+    write(REG_ESR, 0);
+    eoi();
+}
+
+pub fn supports_x2apic() -> bool {
+    let (_, _, ecx, _) = cpuid(1, 0);
+    ecx & (1 << 21) != 0
+}
+
+pub fn is_x2apic() -> bool {
+    unsafe { X2APIC }
+}
+
+/// The APIC ID of the current processor
+pub fn id() -> u32 {
+    if is_x2apic() {
+        read(REG_ID)
+    } else {
+        read(REG_ID) >> 24
+    }
+}
+
+pub fn version() -> u8 {
+    read(REG_VERSION) as u8
+}
+
+/// Signals the end of an interrupt, has to be sent by every handler except the spurious one
+pub fn eoi() {
+    write(REG_EOI, 0);
+}
+
+pub fn send_ipi(apic_id: u32, ipi: Ipi) {
+    send_command(apic_id, ipi.command());
+}
+
+/// Sends `ipi` to every processor except the current one
+pub fn broadcast_ipi(ipi: Ipi) {
+    send_command(0, ipi.command() | ICR_ALL_EXCLUDING_SELF);
+}
+
+fn send_command(destination: u32, command: u32) {
+    if is_x2apic() {
+        // The x2APIC ICR is a single 64-bit MSR and doesn't have a delivery status
+        unsafe {
+            wrmsr(
+                X2APIC_MSR_BASE + (REG_ICR_LOW >> 4),
+                (destination as u64) << 32 | command as u64,
+            );
+        }
+    } else {
+        write(REG_ICR_HIGH, destination << 24);
+        write(REG_ICR_LOW, command);
+
+        while read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+pub fn read(register: u32) -> u32 {
+    if is_x2apic() {
+        unsafe { rdmsr(X2APIC_MSR_BASE + (register >> 4)) as u32 }
+    } else {
+        unsafe { ((BASE + register as VirtAddr) as *const u32).read_volatile() }
+    }
+}
+
+pub fn write(register: u32, value: u32) {
+    if is_x2apic() {
+        unsafe { wrmsr(X2APIC_MSR_BASE + (register >> 4), value as u64) }
+    } else {
+        unsafe { ((BASE + register as VirtAddr) as *mut u32).write_volatile(value) }
+    }
+}
+
+/// Remaps the PICs to vectors 0x20-0x2F, so nothing they raise before being masked lands on an exception
+/// handler, and masks every IRQ on both of them
+fn disable_legacy_pics() {
+    const PIC1_COMMAND: u16 = 0x20;
+    const PIC1_DATA: u16 = 0x21;
+    const PIC2_COMMAND: u16 = 0xA0;
+    const PIC2_DATA: u16 = 0xA1;
+
+    // Writing to an unused port takes long enough for the PICs to keep up
+    let io_wait = || unsafe { outb(0x80, 0) };
+
+    unsafe {
+        // ICW1: initialize, ICW4 follows
+        outb(PIC1_COMMAND, 0x11);
+        io_wait();
+        outb(PIC2_COMMAND, 0x11);
+        io_wait();
+        // ICW2: vector offsets
+        outb(PIC1_DATA, PIC_VECTOR_BASE);
+        io_wait();
+        outb(PIC2_DATA, PIC_VECTOR_BASE + 8);
+        io_wait();
+        // ICW3: the second PIC is cascaded on IRQ 2
+        outb(PIC1_DATA, 1 << 2);
+        io_wait();
+        outb(PIC2_DATA, 2);
+        io_wait();
+        // ICW4: 8086 mode
+        outb(PIC1_DATA, 0x01);
+        io_wait();
+        outb(PIC2_DATA, 0x01);
+        io_wait();
+
+        outb(PIC1_DATA, 0xFF);
+        outb(PIC2_DATA, 0xFF);
+
+        // Read back the mask so the writes are done before the APIC is touched
+        let _ = inb(PIC1_DATA);
+    }
+}
+
+/// Spurious interrupts don't need an EOI or anything else, so the handler just returns
+#[unsafe(naked)]
+extern "C" fn spurious_interrupt() {
+    naked_asm!("iretq");
+}
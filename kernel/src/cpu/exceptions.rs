@@ -0,0 +1,354 @@
+//! Entry stubs and handlers for the CPU exceptions (vectors 0-31).
+//!
+//! Every stub pushes a dummy error code if the CPU doesn't push one, followed by its vector, and jumps to a common
+//! routine that saves the general purpose registers and calls [`exception_dispatch`] with the resulting
+//! [`TrapFrame`]. If the handler returns, the registers are restored from the frame and execution continues.
+//!
+//! The stubs for device interrupts in [`crate::cpu::interrupts`] go through the same routine, vectors past the
+//! exceptions are passed on to [`interrupts::dispatch`].
+
+use crate::cpu::interrupts;
+use crate::mem::is_stack_guard;
+use crate::println;
+use core::arch::{asm, global_asm};
+
+pub const EXCEPTION_COUNT: usize = 32;
+
+/// What the CPU pushes on every interrupt in long mode
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct InterruptStackFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// The registers saved by the exception stubs, in the order they end up on the stack
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// 0 for exceptions without an error code
+    pub error_code: u64,
+    pub frame: InterruptStackFrame,
+}
+
+global_asm!(
+    ".macro exception_stub vector",
+    "exception_stub_\\vector:",
+    "    push 0",
+    "    push \\vector",
+    "    jmp grove_interrupt_common",
+    ".endm",
+    // The CPU already pushed an error code for these
+    ".macro exception_stub_error vector",
+    "exception_stub_\\vector:",
+    "    push \\vector",
+    "    jmp grove_interrupt_common",
+    ".endm",
+    "",
+    ".text",
+    "exception_stub 0",
+    "exception_stub 1",
+    "exception_stub 2",
+    "exception_stub 3",
+    "exception_stub 4",
+    "exception_stub 5",
+    "exception_stub 6",
+    "exception_stub 7",
+    "exception_stub_error 8",
+    "exception_stub 9",
+    "exception_stub_error 10",
+    "exception_stub_error 11",
+    "exception_stub_error 12",
+    "exception_stub_error 13",
+    "exception_stub_error 14",
+    "exception_stub 15",
+    "exception_stub 16",
+    "exception_stub_error 17",
+    "exception_stub 18",
+    "exception_stub 19",
+    "exception_stub 20",
+    "exception_stub_error 21",
+    "exception_stub 22",
+    "exception_stub 23",
+    "exception_stub 24",
+    "exception_stub 25",
+    "exception_stub 26",
+    "exception_stub 27",
+    "exception_stub 28",
+    "exception_stub_error 29",
+    "exception_stub_error 30",
+    "exception_stub 31",
+    "",
+    // The CPU aligns the stack to 16 bytes before pushing the interrupt frame, and the frame, error code, vector
+    // and saved registers add up to a multiple of 16, so the stack is aligned for the call
+    ".global grove_interrupt_common",
+    ".hidden grove_interrupt_common",
+    "grove_interrupt_common:",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    "    cld",
+    "    call {dispatch}",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    // Vector and error code
+    "    add rsp, 16",
+    "    iretq",
+    "",
+    ".section .data.rel.ro.exception_stubs, \"aw\"",
+    ".balign 8",
+    ".global grove_exception_stubs",
+    "grove_exception_stubs:",
+    ".irp vector, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "    .quad exception_stub_\\vector",
+    ".endr",
+    ".text",
+    dispatch = sym exception_dispatch,
+);
+
+unsafe extern "C" {
+    static grove_exception_stubs: [u64; EXCEPTION_COUNT];
+}
+
+/// The address of the entry stub for exception `vector`, to put in the IDT
+pub fn stub(vector: usize) -> u64 {
+    unsafe { grove_exception_stubs[vector] }
+}
+
+const EXCEPTION_NAMES: [&str; EXCEPTION_COUNT] = [
+    "Divide Error (#DE)",
+    "Debug (#DB)",
+    "Non-Maskable Interrupt",
+    "Breakpoint (#BP)",
+    "Overflow (#OF)",
+    "Bound Range Exceeded (#BR)",
+    "Invalid Opcode (#UD)",
+    "Device Not Available (#NM)",
+    "Double Fault (#DF)",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS (#TS)",
+    "Segment Not Present (#NP)",
+    "Stack-Segment Fault (#SS)",
+    "General Protection Fault (#GP)",
+    "Page Fault (#PF)",
+    "Reserved",
+    "x87 Floating-Point Exception (#MF)",
+    "Alignment Check (#AC)",
+    "Machine Check (#MC)",
+    "SIMD Floating-Point Exception (#XM)",
+    "Virtualization Exception (#VE)",
+    "Control Protection Exception (#CP)",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection Exception (#HV)",
+    "VMM Communication Exception (#VC)",
+    "Security Exception (#SX)",
+    "Reserved",
+];
+
+const DEBUG: u64 = 1;
+const BREAKPOINT: u64 = 3;
+const INVALID_TSS: u64 = 10;
+const SEGMENT_NOT_PRESENT: u64 = 11;
+const STACK_SEGMENT_FAULT: u64 = 12;
+const GENERAL_PROTECTION_FAULT: u64 = 13;
+const PAGE_FAULT: u64 = 14;
+
+extern "sysv64" fn exception_dispatch(frame: &mut TrapFrame) {
+    match frame.vector {
+        vector if vector >= EXCEPTION_COUNT as u64 => interrupts::dispatch(frame),
+        // Both are used on purpose (int3, single stepping, hardware breakpoints), so execution carries on after
+        // them. For int3, rip already points past the instruction.
+        DEBUG | BREAKPOINT => {
+            println!(
+                "{} at {:#x}",
+                EXCEPTION_NAMES[frame.vector as usize], frame.frame.rip
+            );
+        }
+        _ => {
+            println!("---------- {} ----------", exception_name(frame.vector));
+            print_error_code(frame);
+            dump_registers(frame);
+
+            loop {
+                unsafe { asm!("cli; hlt") }
+            }
+        }
+    }
+}
+
+fn exception_name(vector: u64) -> &'static str {
+    EXCEPTION_NAMES
+        .get(vector as usize)
+        .copied()
+        .unwrap_or("Unknown")
+}
+
+fn print_error_code(frame: &TrapFrame) {
+    let error_code = frame.error_code;
+
+    match frame.vector {
+        PAGE_FAULT => {
+            let address = read_cr2();
+            println!(
+                "{} {} address {:#x} from {} mode{}{}",
+                if error_code & (1 << 0) != 0 {
+                    "Protection violation"
+                } else {
+                    "Non-present page"
+                },
+                if error_code & (1 << 4) != 0 {
+                    "fetching instruction at"
+                } else if error_code & (1 << 1) != 0 {
+                    "writing"
+                } else {
+                    "reading"
+                },
+                address,
+                if error_code & (1 << 2) != 0 {
+                    "user"
+                } else {
+                    "kernel"
+                },
+                if error_code & (1 << 3) != 0 {
+                    ", reserved bit set"
+                } else {
+                    ""
+                },
+                if error_code & (1 << 5) != 0 {
+                    ", protection key"
+                } else {
+                    ""
+                },
+            );
+
+            if is_stack_guard(address) {
+                println!("Kernel stack overflow");
+            }
+        }
+        INVALID_TSS | SEGMENT_NOT_PRESENT | STACK_SEGMENT_FAULT | GENERAL_PROTECTION_FAULT
+            if error_code != 0 =>
+        {
+            let table = match (error_code >> 1) & 0b11 {
+                0b00 => "GDT",
+                0b10 => "LDT",
+                _ => "IDT",
+            };
+            println!(
+                "Selector error: {} index {:#x}{}",
+                table,
+                (error_code >> 3) & 0x1FFF,
+                if error_code & 1 != 0 {
+                    " (external)"
+                } else {
+                    ""
+                }
+            );
+        }
+        _ => println!("Error code: {:#x}", error_code),
+    }
+}
+
+fn dump_registers(frame: &TrapFrame) {
+    let (ds, es, fs, gs): (u16, u16, u16, u16);
+    let (cr0, cr3, cr4): (u64, u64, u64);
+    unsafe {
+        asm!("mov {:x}, ds", out(reg) ds, options(nomem, nostack, preserves_flags));
+        asm!("mov {:x}, es", out(reg) es, options(nomem, nostack, preserves_flags));
+        asm!("mov {:x}, fs", out(reg) fs, options(nomem, nostack, preserves_flags));
+        asm!("mov {:x}, gs", out(reg) gs, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+
+    let stack = &frame.frame;
+    println!(
+        "RIP={:016x} (unslid {:016x}) RFLAGS={:016x}",
+        stack.rip,
+        crate::mem::unslide(stack.rip),
+        stack.rflags
+    );
+    println!(
+        "RAX={:016x} RBX={:016x} RCX={:016x} RDX={:016x}",
+        frame.rax, frame.rbx, frame.rcx, frame.rdx
+    );
+    println!(
+        "RSI={:016x} RDI={:016x} RBP={:016x} RSP={:016x}",
+        frame.rsi, frame.rdi, frame.rbp, stack.rsp
+    );
+    println!(
+        "R8 ={:016x} R9 ={:016x} R10={:016x} R11={:016x}",
+        frame.r8, frame.r9, frame.r10, frame.r11
+    );
+    println!(
+        "R12={:016x} R13={:016x} R14={:016x} R15={:016x}",
+        frame.r12, frame.r13, frame.r14, frame.r15
+    );
+    println!(
+        "CS={:04x} SS={:04x} DS={:04x} ES={:04x} FS={:04x} GS={:04x}",
+        stack.cs, stack.ss, ds, es, fs, gs
+    );
+    println!(
+        "CR0={:016x} CR2={:016x} CR3={:016x} CR4={:016x}",
+        cr0,
+        read_cr2(),
+        cr3,
+        cr4
+    );
+}
+
+fn read_cr2() -> u64 {
+    let cr2: u64;
+    unsafe { asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags)) }
+    cr2
+}
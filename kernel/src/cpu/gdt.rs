@@ -1,3 +1,4 @@
+use crate::cpu::tss;
 use core::arch::{asm, naked_asm};
 
 #[repr(packed)]
@@ -33,9 +34,24 @@ impl GDTEntry {
             granularity: granularity << 4 | ((limit >> 16) & 0x0F) as u8,
         }
     }
+
+    /// A 64-bit TSS descriptor takes up two entries, the second one holds the upper half of the base
+    pub fn tss(base: u64, limit: u32) -> [Self; 2] {
+        let high = Self {
+            limit: ((base >> 32) & 0xFFFF) as _,
+            base_low: ((base >> 48) & 0xFFFF) as _,
+            ..Self::empty()
+        };
+
+        // 0x89: present, available 64-bit TSS
+        [Self::new(base as u32, limit, 0x89, 0x0), high]
+    }
 }
 
-const GDT_ENTRIES: usize = 5;
+const GDT_ENTRIES: usize = 7;
+/// Selector of the TSS descriptor, which takes up entries 5 and 6
+pub const TSS_SELECTOR: u16 = 0x28;
+
 static mut GDT: [GDTEntry; GDT_ENTRIES] = [GDTEntry::empty(); GDT_ENTRIES];
 
 pub fn install_gdt_defaults() {
@@ -44,6 +60,10 @@ pub fn install_gdt_defaults() {
         GDT[2] = GDTEntry::new(0, 0xFFFF, 0x92, 0xC);
         GDT[3] = GDTEntry::new(0, 0xFFFF, 0xFA, 0xA);
         GDT[4] = GDTEntry::new(0, 0xFFFF, 0xF2, 0xC);
+
+        let [low, high] = GDTEntry::tss(tss::init(), tss::limit());
+        GDT[5] = low;
+        GDT[6] = high;
     }
 }
 
@@ -67,7 +87,8 @@ pub extern "C" fn lgdt() {
             GDTP.base = &raw const GDT as u64;
 
             asm!("cli");
-            asm!("lgdt [{gdtp}]", gdtp = in(reg) &raw const GDTP, options(nostack))
+            asm!("lgdt [{gdtp}]", gdtp = in(reg) &raw const GDTP, options(nostack));
+            asm!("ltr {selector:x}", selector = in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
         }
     }
 
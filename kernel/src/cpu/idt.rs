@@ -1,8 +1,9 @@
+use crate::cpu::apic;
+use crate::cpu::exceptions::{self, EXCEPTION_COUNT};
+use crate::cpu::interrupts;
+use crate::cpu::tss;
 use core::arch::asm;
 
-type ISR = unsafe extern "x86-interrupt" fn(*mut ());
-type ISR_ERR = unsafe extern "x86-interrupt" fn(*mut (), u64);
-
 #[repr(packed)]
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
@@ -29,13 +30,12 @@ impl IDTEntry {
         }
     }
 
-    pub fn new(isr: ISR, flags: Option<u8>) -> Self {
-        let isr = isr as *const () as u64;
-
+    /// An entry calling the handler at `handler`, which has to be an entry stub that ends in `iretq`
+    pub fn new(handler: u64, flags: Option<u8>) -> Self {
         Self {
-            offset_low: (isr & 0xFFFF) as _,
-            offset_mid: ((isr >> 16) & 0xFFFF) as _,
-            offset_high: ((isr >> 32) & 0xFFFFFFFF) as _,
+            offset_low: (handler & 0xFFFF) as _,
+            offset_mid: ((handler >> 16) & 0xFFFF) as _,
+            offset_high: ((handler >> 32) & 0xFFFFFFFF) as _,
             selector: 0x08,
             ist: 0x00,
             type_attr: flags.unwrap_or(0x8E),
@@ -43,18 +43,10 @@ impl IDTEntry {
         }
     }
 
-    pub fn new_error(isr: ISR_ERR, flags: Option<u8>) -> Self {
-        let isr = isr as *const () as u64;
-
-        Self {
-            offset_low: (isr & 0xFFFF) as _,
-            offset_mid: ((isr >> 16) & 0xFFFF) as _,
-            offset_high: ((isr >> 32) & 0xFFFFFFFF) as _,
-            selector: 0x08,
-            ist: 0x00,
-            type_attr: flags.unwrap_or(0x8E),
-            zero: 0,
-        }
+    /// Makes the CPU switch to the stack at `ist` (1-7) in the TSS before calling the handler, 0 keeps the current stack
+    pub fn with_ist(mut self, ist: u8) -> Self {
+        self.ist = ist & 0b111;
+        self
     }
 }
 
@@ -87,82 +79,24 @@ pub fn lidt() {
         IDTP.base = &raw const IDT as u64;
 
         asm!("lidt [{idtp}]", idtp = in(reg) &raw const IDTP, options(nostack, preserves_flags));
-        asm!("sti")
     }
 }
 
 pub fn setup_idt() {
     use IDTEntry as E;
 
-    set_idt_entry(E::new(divide_error, None), 0);              // #DE
-    set_idt_entry(E::new(debug, None), 1);                     // #DB
-    set_idt_entry(E::new(non_maskable, None), 2);              // NMI
-    set_idt_entry(E::new(breakpoint, None), 3);                // #BP
-    set_idt_entry(E::new(overflow, None), 4);                  // #OF
-    set_idt_entry(E::new(bound_range, None), 5);               // #BR
-    set_idt_entry(E::new(invalid_opcode, None), 6);            // #UD
-    set_idt_entry(E::new(device_not_available, None), 7);      // #NM
-    set_idt_entry(E::new(double_fault, None), 8);              // #DF
-    // Skipping 9 (obsolete: Coprocessor Segment Overrun)
-    set_idt_entry(E::new(invalid_tss, None), 10);              // #TS
-    set_idt_entry(E::new(segment_not_present, None), 11);      // #NP
-    set_idt_entry(E::new(stack_segment_fault, None), 12);      // #SS
-    set_idt_entry(E::new(general_protection_fault, None), 13); // #GP
-    set_idt_entry(E::new_error(page_fault, None), 14);         // #PF
-    set_idt_entry(E::new(x87_floating_point, None), 16);       // #MF
-    set_idt_entry(E::new(alignment_check, None), 17);          // #AC
-    set_idt_entry(E::new(machine_check, None), 18);            // #MC
-    set_idt_entry(E::new(simd, None), 19);                     // #XM
-    set_idt_entry(E::new(virtualization, None), 20);           // #VE
-    set_idt_entry(E::new(security_exception, None), 30);       // #CP
-}
-
-macro_rules! isr {
-    ($interrupt:ident) => {
-        #[inline]
-        pub extern "x86-interrupt" fn $interrupt(_stack_frame: *mut ()) {
-            crate::println!("{} interrupt", stringify!($interrupt));
-
-            loop {
-                unsafe {
-                    asm!("hlt")
-                }
-            }
-        }
-    };
-}
-
-isr!(divide_error);
-isr!(debug);
-isr!(non_maskable);
-isr!(breakpoint);
-isr!(overflow);
-isr!(bound_range);
-isr!(invalid_opcode);
-isr!(device_not_available);
-isr!(double_fault);
-isr!(invalid_tss);
-isr!(segment_not_present);
-isr!(stack_segment_fault);
-isr!(general_protection_fault);
-
-pub extern "x86-interrupt" fn page_fault(_stack_frame: *mut (), error_code: u64) {
-    crate::println!("page fault");
-
-    let cr2: u64;
-    unsafe { asm!("mov {}, cr2", out(reg) cr2, options(nostack, preserves_flags)) }
-
-    crate::println!("faulting address {:#x}", cr2);
-    crate::println!("errorcode: {:#x}", error_code);
+    // Every exception goes through its stub in cpu::exceptions, which takes care of the error code
+    for vector in 0..EXCEPTION_COUNT {
+        set_idt_entry(E::new(exceptions::stub(vector), None), vector);
+    }
 
-    loop {
-        unsafe { asm!("hlt") }
+    // Device interrupts all go through the registry in cpu::interrupts, the spurious vector is set up by the APIC
+    for vector in EXCEPTION_COUNT..apic::SPURIOUS_VECTOR as usize {
+        set_idt_entry(E::new(interrupts::stub(vector), None), vector);
     }
-}
 
-isr!(x87_floating_point);
-isr!(alignment_check);
-isr!(machine_check);
-isr!(simd);
-isr!(virtualization);
-isr!(security_exception);
+    set_idt_entry(E::new(exceptions::stub(2), None).with_ist(tss::IST_NMI), 2); // NMI
+    set_idt_entry(E::new(exceptions::stub(8), None).with_ist(tss::IST_DOUBLE_FAULT), 8); // #DF
+    set_idt_entry(E::new(exceptions::stub(14), None).with_ist(tss::IST_PAGE_FAULT), 14); // #PF
+    set_idt_entry(E::new(exceptions::stub(18), None).with_ist(tss::IST_MACHINE_CHECK), 18); // #MC
+}
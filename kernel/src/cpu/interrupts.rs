@@ -0,0 +1,222 @@
+//! Vectors for device interrupts (0x20-0xFE) and the handlers drivers attach to them.
+//!
+//! Every vector has an entry stub that goes through the same register saving routine as the exceptions, after
+//! which [`dispatch`] calls the vector's handlers and sends the EOI. Vectors can be shared, in which case every
+//! handler is called and each one checks whether its device raised the interrupt.
+//!
+//! The vectors the 8259 PICs are remapped to only ever see spurious PIC interrupts, which are dropped without an
+//! EOI since the local APIC didn't deliver them.
+
+use crate::cpu::apic;
+use crate::cpu::exceptions::{EXCEPTION_COUNT, TrapFrame};
+use crate::println;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+
+/// Where the masked 8259 PICs are remapped to, they can still raise spurious interrupts there
+const PIC_VECTORS: core::ops::Range<usize> = 0x20..0x30;
+/// Vectors below this are left alone by [`allocate_vector`]
+const FIRST_ALLOCATABLE_VECTOR: usize = PIC_VECTORS.end;
+const VECTOR_COUNT: usize = 256;
+
+/// Each entry stub is aligned to this, so stub `n` is at a fixed offset from the first one
+const STUB_ALIGN: u64 = 16;
+
+global_asm!(
+    ".text",
+    ".balign 16",
+    ".global grove_interrupt_stubs",
+    ".hidden grove_interrupt_stubs",
+    "grove_interrupt_stubs:",
+    ".set vector, 32",
+    // Every vector from the first one after the exceptions up to the spurious vector, which has its own handler
+    ".rept 223",
+    "    .balign 16",
+    "    push 0",
+    "    push vector",
+    "    jmp grove_interrupt_common",
+    "    .set vector, vector + 1",
+    ".endr",
+);
+
+unsafe extern "C" {
+    static grove_interrupt_stubs: u8;
+}
+
+/// The address of the entry stub for `vector`, which has to be past the exceptions and below the spurious vector
+pub fn stub(vector: usize) -> u64 {
+    debug_assert!((EXCEPTION_COUNT..apic::SPURIOUS_VECTOR as usize).contains(&vector));
+
+    &raw const grove_interrupt_stubs as u64 + (vector - EXCEPTION_COUNT) as u64 * STUB_ALIGN
+}
+
+#[derive(Debug)]
+pub enum InterruptError {
+    /// Every allocatable vector is in use
+    NoFreeVector,
+    /// Exceptions, the PIC vectors and the spurious vector can't have handlers attached
+    ReservedVector(u8),
+    /// The vector still has handlers attached
+    VectorInUse(u8),
+    UnknownHandler,
+}
+
+/// Something that handles interrupts on a vector. Returns whether the interrupt came from its device, which only
+/// matters for shared vectors.
+///
+/// Handlers run with interrupts disabled and shouldn't send the EOI themselves, [`dispatch`] does that. They must not
+/// call [`register`] or [`unregister`] either, [`dispatch`] is still going through the vector's handlers.
+pub trait InterruptHandler: Send + Sync {
+    fn handle(&self, frame: &mut TrapFrame) -> bool;
+}
+
+impl<F: Fn(&mut TrapFrame) -> bool + Send + Sync> InterruptHandler for F {
+    fn handle(&self, frame: &mut TrapFrame) -> bool {
+        self(frame)
+    }
+}
+
+/// Returned by [`register`], used to detach the handler again
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HandlerId {
+    vector: u8,
+    id: u32,
+}
+
+impl HandlerId {
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+}
+
+struct Registration {
+    id: u32,
+    handler: Box<dyn InterruptHandler>,
+}
+
+static mut HANDLERS: [Vec<Registration>; VECTOR_COUNT] = [const { Vec::new() }; VECTOR_COUNT];
+/// Vectors handed out by [`allocate_vector`] or with handlers attached, one bit per vector
+static mut ALLOCATED: [u64; VECTOR_COUNT / 64] = [0; VECTOR_COUNT / 64];
+static mut NEXT_ID: u32 = 0;
+
+/// Fails for vectors that can't have handlers attached or interrupts routed to, [`dispatch`] sends no EOI for them
+pub(crate) fn check_vector(vector: u8) -> Result<(), InterruptError> {
+    if (vector as usize) < EXCEPTION_COUNT
+        || PIC_VECTORS.contains(&(vector as usize))
+        || vector == apic::SPURIOUS_VECTOR
+    {
+        return Err(InterruptError::ReservedVector(vector));
+    }
+
+    Ok(())
+}
+
+fn is_allocated(vector: u8) -> bool {
+    unsafe { ALLOCATED[vector as usize / 64] & (1 << (vector % 64)) != 0 }
+}
+
+fn set_allocated(vector: u8, allocated: bool) {
+    unsafe {
+        ALLOCATED[vector as usize / 64] &= !(1 << (vector % 64));
+        if allocated {
+            ALLOCATED[vector as usize / 64] |= 1 << (vector % 64);
+        }
+    }
+}
+
+/// Hands out a vector nobody else uses, for a driver to route its interrupt to
+pub fn allocate_vector() -> Result<u8, InterruptError> {
+    crate::cpu::without_interrupts(|| {
+        let vector = (FIRST_ALLOCATABLE_VECTOR..apic::SPURIOUS_VECTOR as usize)
+            .map(|vector| vector as u8)
+            .find(|vector| !is_allocated(*vector))
+            .ok_or(InterruptError::NoFreeVector)?;
+        set_allocated(vector, true);
+
+        Ok(vector)
+    })
+}
+
+/// Gives a vector back once every handler has been detached from it
+pub fn free_vector(vector: u8) -> Result<(), InterruptError> {
+    check_vector(vector)?;
+
+    crate::cpu::without_interrupts(|| {
+        #[allow(static_mut_refs)]
+        if unsafe { !HANDLERS[vector as usize].is_empty() } {
+            return Err(InterruptError::VectorInUse(vector));
+        }
+        set_allocated(vector, false);
+
+        Ok(())
+    })
+}
+
+/// Attaches `handler` to `vector`, next to any handlers that are already there. The vector counts as allocated
+/// until it's freed again, even if it wasn't handed out by [`allocate_vector`].
+pub fn register(
+    vector: u8,
+    handler: impl InterruptHandler + 'static,
+) -> Result<HandlerId, InterruptError> {
+    register_boxed(vector, Box::new(handler))
+}
+
+pub fn register_boxed(
+    vector: u8,
+    handler: Box<dyn InterruptHandler>,
+) -> Result<HandlerId, InterruptError> {
+    check_vector(vector)?;
+
+    // The handler list is only ever touched with interrupts off, so dispatch never sees it half updated
+    crate::cpu::without_interrupts(|| unsafe {
+        let id = NEXT_ID;
+        NEXT_ID = NEXT_ID.wrapping_add(1);
+
+        #[allow(static_mut_refs)]
+        HANDLERS[vector as usize].push(Registration { id, handler });
+        set_allocated(vector, true);
+
+        Ok(HandlerId { vector, id })
+    })
+}
+
+/// Detaches a handler, the vector stays allocated until [`free_vector`] is called
+pub fn unregister(handler: HandlerId) -> Result<(), InterruptError> {
+    crate::cpu::without_interrupts(|| {
+        #[allow(static_mut_refs)]
+        let handlers = unsafe { &mut HANDLERS[handler.vector as usize] };
+
+        let index = handlers
+            .iter()
+            .position(|registration| registration.id == handler.id)
+            .ok_or(InterruptError::UnknownHandler)?;
+        handlers.remove(index);
+
+        Ok(())
+    })
+}
+
+/// Called by the common entry routine for every vector past the exceptions
+pub fn dispatch(frame: &mut TrapFrame) {
+    let vector = frame.vector as usize;
+
+    // A spurious interrupt from the masked PICs, the local APIC has nothing to acknowledge
+    if PIC_VECTORS.contains(&vector) {
+        return;
+    }
+
+    #[allow(static_mut_refs)]
+    let handlers = unsafe { &HANDLERS[vector] };
+
+    // Shared vectors call every handler, since more than one device can be raising the interrupt at once
+    let handled = handlers.iter().fold(false, |handled, registration| {
+        registration.handler.handle(frame) | handled
+    });
+
+    if !handled {
+        println!("Unhandled interrupt on vector {:#x}", vector);
+    }
+
+    apic::eoi();
+}
@@ -0,0 +1,301 @@
+//! The IOAPICs and the legacy ISA IRQ overrides, both discovered from the ACPI MADT.
+//!
+//! Every redirection entry starts out masked, drivers route the IRQ or GSI they need to a vector with
+//! [`route_isa_irq`] or [`route_gsi`].
+
+use crate::acpi;
+use crate::acpi::madt::{MadtEntry, MpsIntiFlags};
+use crate::cpu::interrupts;
+use crate::mem::page::VirtAddr;
+use crate::mem::phys_to_virt;
+use crate::println;
+
+const MAX_IO_APICS: usize = 8;
+const ISA_IRQS: usize = 16;
+
+const REG_ID: u32 = 0x00;
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION_TABLE: u32 = 0x10;
+
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL_TRIGGERED: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+
+#[derive(Debug)]
+pub enum IoApicError {
+    NoMadt,
+    NoIoApic,
+    /// None of the IOAPICs handles this GSI
+    NoSuchGsi(u32),
+    /// ISA only has IRQs 0-15
+    NoSuchIrq(u8),
+    /// The IOAPIC destination field only has room for 8-bit APIC IDs
+    DestinationOutOfRange(u32),
+    /// The vector is an exception, a PIC vector or the spurious vector, none of which get an EOI
+    ReservedVector(u8),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// Where an interrupt comes in and how it's signalled
+#[derive(Copy, Clone, Debug)]
+pub struct IrqRoute {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+impl IrqRoute {
+    /// PCI INTx lines are shared, so they're always level triggered and active low
+    pub const fn pci(gsi: u32) -> Self {
+        Self {
+            gsi,
+            polarity: Polarity::ActiveLow,
+            trigger: TriggerMode::Level,
+        }
+    }
+
+    /// Applies the polarity and trigger mode from a MADT entry, keeping the current ones where it says the bus
+    /// default applies
+    fn with_flags(mut self, flags: MpsIntiFlags) -> Self {
+        match flags.active_low() {
+            Some(true) => self.polarity = Polarity::ActiveLow,
+            Some(false) => self.polarity = Polarity::ActiveHigh,
+            None => {}
+        }
+        match flags.level_triggered() {
+            Some(true) => self.trigger = TriggerMode::Level,
+            Some(false) => self.trigger = TriggerMode::Edge,
+            None => {}
+        }
+
+        self
+    }
+}
+
+#[derive(Copy, Clone)]
+struct IoApic {
+    id: u8,
+    /// Direct map address of the registers
+    base: VirtAddr,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            (self.base as *mut u32).write_volatile(register);
+            ((self.base + 0x10) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            (self.base as *mut u32).write_volatile(register);
+            ((self.base + 0x10) as *mut u32).write_volatile(value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn read_entry(&self, index: u32) -> u64 {
+        let low = self.read(REG_REDIRECTION_TABLE + index * 2);
+        let high = self.read(REG_REDIRECTION_TABLE + index * 2 + 1);
+
+        (high as u64) << 32 | low as u64
+    }
+
+    fn write_entry(&self, index: u32, entry: u64) {
+        // Writing the masked low half last keeps a half written entry from firing, and unmasks it only once the
+        // destination is in place
+        self.write(REG_REDIRECTION_TABLE + index * 2, ENTRY_MASKED as u32);
+        self.write(REG_REDIRECTION_TABLE + index * 2 + 1, (entry >> 32) as u32);
+        self.write(REG_REDIRECTION_TABLE + index * 2, entry as u32);
+    }
+}
+
+static mut IO_APICS: [Option<IoApic>; MAX_IO_APICS] = [None; MAX_IO_APICS];
+/// How each ISA IRQ is wired up, identity mapped and edge triggered active high unless the MADT overrides it
+static mut ISA_ROUTES: [IrqRoute; ISA_IRQS] = {
+    let mut routes = [IrqRoute {
+        gsi: 0,
+        polarity: Polarity::ActiveHigh,
+        trigger: TriggerMode::Edge,
+    }; ISA_IRQS];
+
+    let mut irq = 0;
+    while irq < ISA_IRQS {
+        routes[irq].gsi = irq as u32;
+        irq += 1;
+    }
+
+    routes
+};
+
+/// Finds the IOAPICs and ISA overrides in the MADT and masks every redirection entry
+pub fn init() -> Result<(), IoApicError> {
+    let madt = acpi::madt().ok_or(IoApicError::NoMadt)?;
+
+    let mut count = 0;
+    for entry in madt.entries() {
+        match entry {
+            MadtEntry::IoApic {
+                id,
+                address,
+                gsi_base,
+            } if count < MAX_IO_APICS => {
+                let mut io_apic = IoApic {
+                    id,
+                    base: phys_to_virt(address as u64),
+                    gsi_base,
+                    entries: 0,
+                };
+                io_apic.entries = ((io_apic.read(REG_VERSION) >> 16) & 0xFF) + 1;
+
+                for index in 0..io_apic.entries {
+                    io_apic.write_entry(index, ENTRY_MASKED);
+                }
+
+                unsafe { IO_APICS[count] = Some(io_apic) };
+                count += 1;
+            }
+            MadtEntry::IoApic { id, gsi_base, .. } => println!(
+                "Ignoring IOAPIC {} (GSI base {}), only {} are supported",
+                id, gsi_base, MAX_IO_APICS
+            ),
+            MadtEntry::InterruptSourceOverride {
+                bus: 0,
+                source,
+                gsi,
+                flags,
+            } if (source as usize) < ISA_IRQS => unsafe {
+                let route = IrqRoute {
+                    gsi,
+                    ..ISA_ROUTES[source as usize]
+                };
+                ISA_ROUTES[source as usize] = route.with_flags(flags);
+            },
+            _ => {}
+        }
+    }
+
+    if count == 0 {
+        return Err(IoApicError::NoIoApic);
+    }
+
+    Ok(())
+}
+
+fn io_apics() -> impl Iterator<Item = &'static IoApic> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        IO_APICS.iter().flatten()
+    }
+}
+
+fn find(gsi: u32) -> Result<&'static IoApic, IoApicError> {
+    io_apics()
+        .find(|io_apic| io_apic.handles(gsi))
+        .ok_or(IoApicError::NoSuchGsi(gsi))
+}
+
+/// Where the legacy ISA `irq` is wired to, taking the MADT's overrides into account. `None` if `irq` isn't an ISA
+/// IRQ.
+pub fn isa_irq(irq: u8) -> Option<IrqRoute> {
+    if (irq as usize) < ISA_IRQS {
+        Some(unsafe { ISA_ROUTES[irq as usize] })
+    } else {
+        None
+    }
+}
+
+/// Routes the ISA `irq` to `vector` on the processor with APIC ID `apic_id` and unmasks it, returning the GSI it
+/// ended up on
+pub fn route_isa_irq(irq: u8, vector: u8, apic_id: u32) -> Result<u32, IoApicError> {
+    let route = isa_irq(irq).ok_or(IoApicError::NoSuchIrq(irq))?;
+    route_gsi(route, vector, apic_id)?;
+
+    Ok(route.gsi)
+}
+
+/// Routes `route.gsi` to `vector` on the processor with APIC ID `apic_id`, in physical destination mode with fixed
+/// delivery, and unmasks it
+pub fn route_gsi(route: IrqRoute, vector: u8, apic_id: u32) -> Result<(), IoApicError> {
+    interrupts::check_vector(vector).map_err(|_| IoApicError::ReservedVector(vector))?;
+    if apic_id > 0xFF {
+        return Err(IoApicError::DestinationOutOfRange(apic_id));
+    }
+
+    let io_apic = find(route.gsi)?;
+
+    let mut entry = vector as u64 | (apic_id as u64) << 56;
+    if route.polarity == Polarity::ActiveLow {
+        entry |= ENTRY_ACTIVE_LOW;
+    }
+    if route.trigger == TriggerMode::Level {
+        entry |= ENTRY_LEVEL_TRIGGERED;
+    }
+
+    io_apic.write_entry(route.gsi - io_apic.gsi_base, entry);
+
+    Ok(())
+}
+
+pub fn mask(gsi: u32) -> Result<(), IoApicError> {
+    set_masked(gsi, true)
+}
+
+pub fn unmask(gsi: u32) -> Result<(), IoApicError> {
+    set_masked(gsi, false)
+}
+
+fn set_masked(gsi: u32, masked: bool) -> Result<(), IoApicError> {
+    let io_apic = find(gsi)?;
+    let index = gsi - io_apic.gsi_base;
+
+    let entry = io_apic.read_entry(index) & !ENTRY_MASKED;
+    io_apic.write_entry(index, if masked { entry | ENTRY_MASKED } else { entry });
+
+    Ok(())
+}
+
+pub fn print_io_apics() {
+    for io_apic in io_apics() {
+        println!(
+            "IOAPIC {} (hardware ID {}) @ {:#x}: GSIs {}-{}",
+            io_apic.id,
+            (io_apic.read(REG_ID) >> 24) & 0xF,
+            io_apic.base,
+            io_apic.gsi_base,
+            io_apic.gsi_base + io_apic.entries - 1
+        );
+    }
+
+    for irq in 0..ISA_IRQS as u8 {
+        let Some(route) = isa_irq(irq) else {
+            continue;
+        };
+        if route.gsi != irq as u32
+            || route.polarity != Polarity::ActiveHigh
+            || route.trigger != TriggerMode::Edge
+        {
+            println!(
+                "ISA IRQ {} -> GSI {} ({:?}, {:?})",
+                irq, route.gsi, route.polarity, route.trigger
+            );
+        }
+    }
+}
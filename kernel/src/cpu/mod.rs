@@ -2,8 +2,14 @@ use crate::println;
 use core::arch::asm;
 use core::cell::LazyCell;
 
+pub mod apic;
+pub mod exceptions;
 pub mod gdt;
 pub mod idt;
+pub mod interrupts;
+pub mod ioapic;
+pub mod topology;
+pub mod tss;
 
 pub fn print_cpu_info() {
     println!("---------- CPU Info ----------");
@@ -39,6 +45,88 @@ pub fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
     (eax, ebx, ecx, edx)
 }
 
+/// Reads the time stamp counter
+#[inline(always)]
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+
+    // SAFETY: rdtsc only reads the time stamp counter
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+
+    ((high as u64) << 32) | low as u64
+}
+
+/// # Safety
+/// `msr` has to exist on this CPU, reading one that doesn't raises #GP.
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+
+    ((high as u64) << 32) | low as u64
+}
+
+/// # Safety
+/// `msr` has to exist on this CPU, and writing `value` to it mustn't break anything the kernel relies on.
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+/// # Safety
+/// Writing to an I/O port can have any side effect the device behind it wants.
+pub unsafe fn outb(port: u16, value: u8) {
+    unsafe {
+        asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// # Safety
+/// Like [`outb`], reading some I/O ports has side effects.
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    unsafe {
+        asm!("in al, dx", in("dx") port, out("al") value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Only safe to call once every vector that can fire has a handler, see [`apic::init`]
+pub fn enable_interrupts() {
+    unsafe { asm!("sti", options(nomem, nostack)) }
+}
+
+pub fn disable_interrupts() {
+    unsafe { asm!("cli", options(nomem, nostack)) }
+}
+
+/// Runs `f` with interrupts disabled, restoring the interrupt flag to what it was afterwards
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let rflags: u64;
+    unsafe { asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags)) };
+
+    disable_interrupts();
+    let result = f();
+    if rflags & (1 << 9) != 0 {
+        enable_interrupts();
+    }
+
+    result
+}
+
 pub fn cpu_brand_string() -> &'static str {
     static mut BRAND: [u8; 49] = [0; 49];
 
@@ -81,7 +169,12 @@ pub fn cpu_vendor_string() -> &'static str {
     }
 }
 
+/// Logical processors per package, from the loader's processor list if there is one and from CPUID otherwise
 pub fn get_num_logical_processors() -> u32 {
+    if let Some(count) = topology::logical_processors_per_package() {
+        return count;
+    }
+
     match *CPU_VENDOR {
         CPUVendor::Intel => {
             let mut logical_processors = 0;
@@ -111,7 +204,12 @@ pub fn get_num_logical_processors() -> u32 {
     }
 }
 
+/// Like [`get_num_logical_processors`], the loader's processor list is preferred over CPUID
 pub fn get_cores_per_socket() -> u32 {
+    if let Some(count) = topology::cores_per_package() {
+        return count;
+    }
+
     match *CPU_VENDOR {
         CPUVendor::Intel => {
             let mut cores = 0;
@@ -0,0 +1,186 @@
+//! The processors in the system as reported by the loader, from the firmware's MP services.
+
+use crate::acpi::madt::{Madt, MadtEntry};
+use crate::println;
+use boot_protocol::{ProcessorInfo, UEFIBootInfo};
+
+const MAX_PROCESSORS: usize = 256;
+
+static mut PROCESSORS: [ProcessorInfo; MAX_PROCESSORS] = [ProcessorInfo::empty(); MAX_PROCESSORS];
+static mut PROCESSORS_LEN: usize = 0;
+/// Processors past [`MAX_PROCESSORS`] that didn't fit
+static mut PROCESSORS_SKIPPED: usize = 0;
+
+/// Copies the processor list out of loader memory
+pub fn init(boot_info: &UEFIBootInfo) {
+    // SAFETY: this runs before the kernel page table is installed, so the loader's identity mapping is still active
+    let processors = unsafe { boot_info.processors() };
+
+    let len = processors.len().min(MAX_PROCESSORS);
+
+    #[allow(static_mut_refs)]
+    unsafe {
+        PROCESSORS[..len].copy_from_slice(&processors[..len]);
+        PROCESSORS_LEN = len;
+        PROCESSORS_SKIPPED = processors.len() - len;
+    }
+}
+
+/// Every processor in the firmware's order, which is the order APs should be started in.
+/// Empty if the firmware had no MP services.
+pub fn processors() -> &'static [ProcessorInfo] {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &PROCESSORS[..PROCESSORS_LEN]
+    }
+}
+
+pub fn is_available() -> bool {
+    !processors().is_empty()
+}
+
+pub fn bsp() -> Option<&'static ProcessorInfo> {
+    processors().iter().find(|processor| processor.is_bsp())
+}
+
+/// The processors other than the BSP that can be started
+pub fn application_processors() -> impl Iterator<Item = &'static ProcessorInfo> {
+    processors()
+        .iter()
+        .filter(|processor| !processor.is_bsp() && processor.is_enabled())
+}
+
+/// The enabled processors in the BSP's package, or `None` without MP services
+pub fn logical_processors_per_package() -> Option<u32> {
+    let package = bsp()?.package;
+
+    Some(
+        processors()
+            .iter()
+            .filter(|processor| processor.package == package && processor.is_enabled())
+            .count() as u32,
+    )
+}
+
+/// The number of distinct enabled cores in the BSP's package, or `None` without MP services
+pub fn cores_per_package() -> Option<u32> {
+    let package = bsp()?.package;
+    let in_package = || {
+        processors()
+            .iter()
+            .filter(move |processor| processor.package == package && processor.is_enabled())
+    };
+
+    // The list is small enough that counting the first thread of every core is fine
+    Some(
+        in_package()
+            .enumerate()
+            .filter(|(index, processor)| {
+                !in_package()
+                    .take(*index)
+                    .any(|other| other.core == processor.core)
+            })
+            .count() as u32,
+    )
+}
+
+/// Compares the processors from the MP services with the MADT's local APICs, printing every processor that only
+/// one of them knows about. Returns whether they agree.
+pub fn cross_check_madt(madt: &Madt) -> bool {
+    let madt_processors = || {
+        madt.entries().filter_map(|entry| match entry {
+            MadtEntry::LocalApic { apic_id, flags, .. } => Some((apic_id as u32, flags)),
+            MadtEntry::LocalX2Apic {
+                x2apic_id, flags, ..
+            } => Some((x2apic_id, flags)),
+            _ => None,
+        })
+    };
+    let usable = |flags: u32| {
+        flags & (MadtEntry::PROCESSOR_ENABLED | MadtEntry::PROCESSOR_ONLINE_CAPABLE) != 0
+    };
+
+    let mut agree = true;
+
+    for processor in processors()
+        .iter()
+        .filter(|processor| processor.is_enabled())
+    {
+        if !madt_processors().any(|(apic_id, flags)| apic_id == processor.apic_id && usable(flags))
+        {
+            println!(
+                "Processor with APIC ID {} is missing from the MADT",
+                processor.apic_id
+            );
+            agree = false;
+        }
+    }
+
+    for (apic_id, flags) in madt_processors() {
+        if flags & MadtEntry::PROCESSOR_ENABLED != 0
+            && !processors()
+                .iter()
+                .any(|processor| processor.apic_id == apic_id)
+        {
+            println!(
+                "MADT processor with APIC ID {} wasn't reported by the MP services",
+                apic_id
+            );
+            agree = false;
+        }
+    }
+
+    agree
+}
+
+pub fn print_topology() {
+    if !is_available() {
+        println!("No processor list from the loader, relying on ACPI and CPUID");
+        return;
+    }
+
+    let enabled = processors()
+        .iter()
+        .filter(|processor| processor.is_enabled())
+        .count();
+    println!(
+        "Processors: {} ({} enabled), BSP APIC ID {}",
+        processors().len(),
+        enabled,
+        bsp().map_or(0, |bsp| bsp.apic_id)
+    );
+
+    for processor in processors() {
+        println!(
+            "  APIC ID {:>3}: package {} core {} thread {}{}{}",
+            processor.apic_id,
+            processor.package,
+            processor.core,
+            processor.thread,
+            if processor.is_enabled() {
+                ""
+            } else {
+                " [disabled]"
+            },
+            if processor.is_healthy() {
+                ""
+            } else {
+                " [unhealthy]"
+            }
+        );
+    }
+
+    let skipped = unsafe { PROCESSORS_SKIPPED };
+    if skipped > 0 {
+        println!(
+            "Ignoring {} processors, at most {} are supported",
+            skipped, MAX_PROCESSORS
+        );
+    }
+
+    if let Some(madt) = crate::acpi::madt()
+        && cross_check_madt(madt)
+    {
+        println!("Processor list matches the MADT");
+    }
+}
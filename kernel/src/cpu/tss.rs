@@ -0,0 +1,70 @@
+//! The task state segment, which in long mode only holds the stacks the CPU switches to on interrupts.
+
+/// IST index of the double fault stack, so a kernel stack overflow doesn't turn into a triple fault
+pub const IST_DOUBLE_FAULT: u8 = 1;
+pub const IST_NMI: u8 = 2;
+pub const IST_MACHINE_CHECK: u8 = 3;
+/// Page faults get their own stack too, since hitting the boot stack's guard page is a page fault
+pub const IST_PAGE_FAULT: u8 = 4;
+
+const IST_STACK_COUNT: usize = 4;
+const IST_STACK_SIZE: usize = 0x4000;
+
+#[repr(C, packed(4))]
+pub struct TaskStateSegment {
+    reserved0: u32,
+    /// Stacks loaded when switching to ring 0-2 from a lower privilege level
+    rsp: [u64; 3],
+    reserved1: u64,
+    /// Interrupt Stack Table, `ist[0]` is IST index 1
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    iomap_base: u16,
+}
+
+#[repr(C, align(16))]
+struct IstStack([u8; IST_STACK_SIZE]);
+
+static mut TSS: TaskStateSegment = TaskStateSegment {
+    reserved0: 0,
+    rsp: [0; 3],
+    reserved1: 0,
+    ist: [0; 7],
+    reserved2: 0,
+    reserved3: 0,
+    // No I/O permission bitmap, the offset points past the end of the TSS
+    iomap_base: size_of::<TaskStateSegment>() as u16,
+};
+
+static mut IST_STACKS: [IstStack; IST_STACK_COUNT] =
+    [const { IstStack([0; IST_STACK_SIZE]) }; IST_STACK_COUNT];
+
+/// Points the IST entries at their stacks and returns the TSS's address, for the GDT descriptor
+pub fn init() -> u64 {
+    #[allow(static_mut_refs)]
+    unsafe {
+        for (index, stack) in IST_STACKS.iter().enumerate() {
+            // Stacks grow down, so the IST entry is the end of the stack
+            TSS.ist[index] = stack.0.as_ptr_range().end as u64;
+        }
+
+        &raw const TSS as u64
+    }
+}
+
+/// The size of the TSS minus 1, as the GDT descriptor expects it
+pub const fn limit() -> u32 {
+    size_of::<TaskStateSegment>() as u32 - 1
+}
+
+/// Sets the stack the CPU switches to when an interrupt or system call comes in from ring 3
+pub fn set_rsp0(rsp: u64) {
+    unsafe {
+        TSS.rsp[0] = rsp;
+    }
+}
+
+pub fn rsp0() -> u64 {
+    unsafe { TSS.rsp[0] }
+}
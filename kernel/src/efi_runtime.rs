@@ -0,0 +1,272 @@
+//! UEFI runtime services, which stay usable after the loader exited boot services.
+//!
+//! The loader switched them to virtual mode with every runtime region in the direct map, so they're called straight
+//! through the table pointer it passed in the boot info.
+
+use crate::update::{BootStateStore, UpdateError};
+use boot_protocol::{BOOT_STATE_VARIABLE, BootState, GROVE_VENDOR_GUID, UEFIBootInfo};
+use core::fmt::{Display, Formatter};
+
+static mut RUNTIME_SERVICES: *const RuntimeServices = core::ptr::null();
+
+/// Longest variable name (in UTF-16 code units, without the terminator) the kernel can pass to the firmware
+const MAX_VARIABLE_NAME_LEN: usize = 64;
+
+const ERROR_BIT: usize = 1 << 63;
+const UNSUPPORTED: usize = ERROR_BIT | 3;
+const BUFFER_TOO_SMALL: usize = ERROR_BIT | 5;
+const NOT_FOUND: usize = ERROR_BIT | 14;
+
+type Status = usize;
+
+#[repr(C)]
+struct TableHeader {
+    signature: u64,
+    revision: u32,
+    header_size: u32,
+    crc32: u32,
+    reserved: u32,
+}
+
+/// `EFI_RUNTIME_SERVICES`, the services the kernel doesn't use are only there to keep the layout right
+#[repr(C)]
+struct RuntimeServices {
+    header: TableHeader,
+    get_time: unsafe extern "efiapi" fn(time: *mut EfiTime, capabilities: *mut u8) -> Status,
+    set_time: usize,
+    get_wakeup_time: usize,
+    set_wakeup_time: usize,
+    set_virtual_address_map: usize,
+    convert_pointer: usize,
+    get_variable: unsafe extern "efiapi" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut u8,
+    ) -> Status,
+    get_next_variable_name: usize,
+    set_variable: unsafe extern "efiapi" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *const u8,
+    ) -> Status,
+    get_next_high_monotonic_count: usize,
+    reset_system: unsafe extern "efiapi" fn(
+        reset_type: ResetType,
+        status: Status,
+        data_size: usize,
+        data: *const u8,
+    ) -> !,
+}
+
+/// `EFI_GUID`, in the byte order UEFI stores it in
+#[repr(C, align(8))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Guid(pub [u8; 16]);
+
+/// `EFI_TIME`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EfiTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pad1: u8,
+    pub nanosecond: u32,
+    /// Offset from UTC in minutes, or [`EfiTime::UNSPECIFIED_TIMEZONE`] for local time
+    pub time_zone: i16,
+    pub daylight: u8,
+    pad2: u8,
+}
+
+impl EfiTime {
+    pub const UNSPECIFIED_TIMEZONE: i16 = 0x07FF;
+}
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ResetType {
+    Cold = 0,
+    Warm = 1,
+    Shutdown = 2,
+}
+
+pub mod attributes {
+    pub const NON_VOLATILE: u32 = 1 << 0;
+    pub const BOOTSERVICE_ACCESS: u32 = 1 << 1;
+    pub const RUNTIME_ACCESS: u32 = 1 << 2;
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EfiError {
+    /// The loader didn't pass the runtime services
+    Unavailable,
+    /// The variable name doesn't fit in [`MAX_VARIABLE_NAME_LEN`] UTF-16 code units
+    NameTooLong,
+    NotFound,
+    /// The variable needs a buffer of this many bytes
+    BufferTooSmall(usize),
+    Unsupported,
+    /// Any other error status returned by the firmware
+    Status(usize),
+}
+
+impl Display for EfiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            EfiError::Unavailable => write!(f, "runtime services unavailable"),
+            EfiError::NameTooLong => write!(f, "variable name too long"),
+            EfiError::NotFound => write!(f, "not found"),
+            EfiError::BufferTooSmall(size) => write!(f, "buffer too small, {} bytes needed", size),
+            EfiError::Unsupported => write!(f, "unsupported"),
+            EfiError::Status(status) => write!(f, "error status {:#x}", status & !ERROR_BIT),
+        }
+    }
+}
+
+pub fn init(boot_info: &UEFIBootInfo) {
+    unsafe {
+        RUNTIME_SERVICES = boot_info.efi_runtime_services as *const RuntimeServices;
+    }
+}
+
+pub fn is_available() -> bool {
+    unsafe { !RUNTIME_SERVICES.is_null() }
+}
+
+fn services() -> Result<&'static RuntimeServices, EfiError> {
+    // SAFETY: the loader passes either null or the virtual address of the runtime services table, which is part of
+    // a runtime region and thus mapped in the direct map
+    unsafe { RUNTIME_SERVICES.as_ref() }.ok_or(EfiError::Unavailable)
+}
+
+fn check(status: Status) -> Result<(), EfiError> {
+    match status {
+        // Warnings don't have the error bit set, the call still did what it was asked
+        status if status & ERROR_BIT == 0 => Ok(()),
+        UNSUPPORTED => Err(EfiError::Unsupported),
+        NOT_FOUND => Err(EfiError::NotFound),
+        status => Err(EfiError::Status(status)),
+    }
+}
+
+/// Encodes `name` as a null terminated UTF-16 string
+fn encode_name(name: &str) -> Result<[u16; MAX_VARIABLE_NAME_LEN + 1], EfiError> {
+    let mut out = [0u16; MAX_VARIABLE_NAME_LEN + 1];
+
+    for (i, unit) in name.encode_utf16().enumerate() {
+        if i >= MAX_VARIABLE_NAME_LEN {
+            return Err(EfiError::NameTooLong);
+        }
+        out[i] = unit;
+    }
+
+    Ok(out)
+}
+
+/// The current time from the firmware's real time clock
+pub fn get_time() -> Result<EfiTime, EfiError> {
+    let services = services()?;
+    let mut time = EfiTime::default();
+
+    // SAFETY: time is a valid EFI_TIME, the capabilities are optional
+    check(unsafe { (services.get_time)(&mut time, core::ptr::null_mut()) })?;
+
+    Ok(time)
+}
+
+/// Reads the variable `name` into `buffer`, returning its size and attributes
+pub fn get_variable(
+    name: &str,
+    vendor: &Guid,
+    buffer: &mut [u8],
+) -> Result<(usize, u32), EfiError> {
+    let services = services()?;
+    let name = encode_name(name)?;
+
+    let mut attributes = 0;
+    let mut size = buffer.len();
+
+    // SAFETY: name is null terminated, and size is the length of buffer
+    let status = unsafe {
+        (services.get_variable)(
+            name.as_ptr(),
+            vendor,
+            &mut attributes,
+            &mut size,
+            buffer.as_mut_ptr(),
+        )
+    };
+
+    if status == BUFFER_TOO_SMALL {
+        return Err(EfiError::BufferTooSmall(size));
+    }
+    check(status)?;
+
+    Ok((size, attributes))
+}
+
+/// Creates, replaces or (with empty `data`) deletes the variable `name`
+pub fn set_variable(
+    name: &str,
+    vendor: &Guid,
+    attributes: u32,
+    data: &[u8],
+) -> Result<(), EfiError> {
+    let services = services()?;
+    let name = encode_name(name)?;
+
+    // SAFETY: name is null terminated, and data is only read for data.len() bytes
+    check(unsafe {
+        (services.set_variable)(name.as_ptr(), vendor, attributes, data.len(), data.as_ptr())
+    })
+}
+
+/// Resets or powers off the machine. Only returns, with the reason, if the runtime services aren't available.
+pub fn reset_system(reset_type: ResetType) -> EfiError {
+    let services = match services() {
+        Ok(services) => services,
+        Err(err) => return err,
+    };
+
+    // SAFETY: no reset data is passed
+    unsafe { (services.reset_system)(reset_type, 0, 0, core::ptr::null()) }
+}
+
+/// Keeps the A/B [`BootState`] in the same UEFI variable the loader reads it from
+pub struct BootStateVariable;
+
+impl BootStateVariable {
+    const VENDOR: Guid = Guid(GROVE_VENDOR_GUID);
+}
+
+impl BootStateStore for BootStateVariable {
+    fn load(&mut self) -> Result<Option<BootState>, UpdateError> {
+        let mut buffer = [0u8; BootState::SIZE];
+
+        match get_variable(BOOT_STATE_VARIABLE, &Self::VENDOR, &mut buffer) {
+            Ok((size, _)) => Ok(BootState::from_bytes(&buffer[..size])),
+            Err(EfiError::NotFound) => Ok(None),
+            Err(_) => Err(UpdateError::StateUnavailable),
+        }
+    }
+
+    fn store(&mut self, state: &BootState) -> Result<(), UpdateError> {
+        let attributes =
+            attributes::NON_VOLATILE | attributes::BOOTSERVICE_ACCESS | attributes::RUNTIME_ACCESS;
+
+        set_variable(
+            BOOT_STATE_VARIABLE,
+            &Self::VENDOR,
+            attributes,
+            &state.to_bytes(),
+        )
+        .map_err(|_| UpdateError::StateUnavailable)
+    }
+}
@@ -2,20 +2,27 @@
 #![no_main]
 #![feature(alloc_error_handler)]
 #![feature(ptr_as_ref_unchecked)]
-#![feature(abi_x86_interrupt)]
 extern crate alloc;
 
+mod acpi;
+mod boot_modules;
+mod cmdline;
 mod cpu;
+mod efi_runtime;
 mod mem;
 mod screen;
+mod time;
+mod update;
 
 use alloc::vec::Vec;
 // use alloc::vec::Vec;
 use crate::cpu::gdt::{install_gdt_defaults, lgdt};
 use crate::cpu::idt::{lidt, setup_idt};
+use crate::mem::heap::PAGE_SIZE;
 use crate::mem::heap::metadata::HeapMetadata;
 use crate::mem::page;
 use crate::screen::{FramebufferWriter, framebuffer_writer, init_writer};
+use boot_protocol::{LoaderTimestamps, UEFIBootInfo};
 use core::arch::asm;
 use core::panic::PanicInfo;
 
@@ -24,36 +31,41 @@ unsafe extern "C" {
     static __kernel_vend: *const u64;
 }
 
-#[repr(C)]
-pub struct UEFIBootInfo {
-    pub framebuffer: *mut u32,
-    pub framebuffer_size: usize,
-    pub framebuffer_width: usize,
-    pub framebuffer_height: usize,
-
-    pub memory_bitmap: *mut u8,
-    pub memory_bitmap_size: usize,
-}
-
 #[unsafe(no_mangle)]
-pub extern "C" fn _start() -> ! {
+pub extern "sysv64" fn _start(boot_info: *const UEFIBootInfo) -> ! {
     // SAFETY: this is okay since were only disabling interrupts
     unsafe {
         asm!("cli");
     }
+    let kernel_entry = cpu::rdtsc();
 
-    // SAFETY: rdi contains the address for the UEFIBootInfo passed in from the bootloader, so dereferencing the pointer is ok
-    let boot_info = unsafe {
-        let boot_info: u64;
-        asm!("mov {boot_info}, rdi", boot_info = out(reg) boot_info);
-
-        (boot_info as *const UEFIBootInfo).read()
+    // SAFETY: the bootloader passes the address of the UEFIBootInfo it built, read_from validates the header before reading the rest
+    let boot_info = match unsafe { UEFIBootInfo::read_from(boot_info) } {
+        Ok(boot_info) => boot_info,
+        // There's no framebuffer to report the error on without a valid boot info, so all we can do is stop here
+        Err(_) => halt(),
     };
 
+    mem::init(&boot_info);
+    cmdline::init(&boot_info);
+    boot_modules::init(&boot_info);
+    cpu::topology::init(&boot_info);
+    update::init(&boot_info);
+    efi_runtime::init(&boot_info);
+
     init_writer(FramebufferWriter::from(&boot_info));
 
     framebuffer_writer().clear();
 
+    cmdline::print_cmdline();
+    println!(
+        "Kernel entry @ {:#x} (KASLR slide {:#x})",
+        _start as usize,
+        mem::kernel_slide()
+    );
+    let stack = mem::boot_stack();
+    println!("Kernel stack @ {:#x}..{:#x}", stack.start, stack.end);
+
     println!("Initializing GDT...");
     install_gdt_defaults();
     lgdt();
@@ -76,6 +88,108 @@ pub extern "C" fn _start() -> ! {
 
     cpu::print_cpu_info();
 
+    match acpi::init(boot_info.acpi_rsdp) {
+        Ok(()) => acpi::print_acpi_info(),
+        Err(err) => println!("Failed to initialize ACPI: {:?}", err),
+    }
+
+    // Everything the kernel needs from loader memory has been copied by now. The RSDP isn't always in an ACPI
+    // region, so this has to wait until ACPI has found the root table through it.
+    let reclaimed = page::reclaim_boot_memory(&boot_info);
+    println!(
+        "Reclaimed {} KiB of boot services and loader memory",
+        reclaimed * PAGE_SIZE as u64 / 1024
+    );
+
+    cpu::topology::print_topology();
+
+    cpu::apic::init();
+    println!(
+        "Local APIC: ID {}, version {:#x}, {}",
+        cpu::apic::id(),
+        cpu::apic::version(),
+        if cpu::apic::is_x2apic() {
+            "x2APIC"
+        } else {
+            "xAPIC"
+        }
+    );
+    match cpu::ioapic::init() {
+        Ok(()) => cpu::ioapic::print_io_apics(),
+        Err(err) => println!("Failed to initialize the IOAPICs: {:?}", err),
+    }
+
+    // The PICs and IOAPICs are masked and the APIC only raises spurious interrupts so far, which have a handler
+    cpu::enable_interrupts();
+
+    time::init();
+    time::print_time_info();
+    if let Err(err) = time::wheel::start() {
+        println!("Failed to start the timer tick: {:?}", err);
+    }
+    print_boot_timings(&boot_info.timestamps, kernel_entry);
+
+    match time::system_time::init() {
+        Ok(()) => println!(
+            "Date: {}",
+            time::system_time::SystemTime::now().date_time()
+        ),
+        Err(err) => println!("Failed to read the RTC: {:?}", err),
+    }
+
+    boot_modules::print_modules();
+
+    match efi_runtime::get_time() {
+        Ok(time) => println!(
+            "EFI time: {:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            time.year, time.month, time.day, time.hour, time.minute, time.second
+        ),
+        Err(err) => println!("EFI runtime services: {}", err),
+    }
+
+    update::print_boot_slot();
+
+    // Getting this far is what counts as a healthy boot for now
+    if update::is_trial_boot() {
+        match update::confirm_boot(&mut efi_runtime::BootStateVariable) {
+            Ok(()) => println!("Confirmed the update in slot {:?}", update::booted_slot()),
+            Err(err) => println!("Failed to confirm the update: {:?}", err),
+        }
+    }
+
+    halt();
+}
+
+/// Prints how long each of the loader's phases took, and how long it took to get from exiting boot services to
+/// the kernel. The loader only records TSC readings, so this is in cycles until the TSC is calibrated.
+fn print_boot_timings(timestamps: &LoaderTimestamps, kernel_entry: u64) {
+    if timestamps.loader_entry == 0 {
+        return;
+    }
+
+    let calibrated = time::tsc::is_calibrated();
+    let print_phase = |phase: &str, cycles: u64| {
+        if calibrated {
+            println!(
+                "  {:<28} {} us",
+                phase,
+                time::tsc::cycles_to_nanos(cycles) / 1000
+            );
+        } else {
+            println!("  {:<28} {} cycles", phase, cycles);
+        }
+    };
+
+    println!("Boot timings:");
+    let mut start = timestamps.loader_entry;
+    for (phase, end) in timestamps.phases() {
+        print_phase(phase, end.wrapping_sub(start));
+        start = end;
+    }
+    print_phase("handoff to kernel", kernel_entry.wrapping_sub(start));
+}
+
+fn halt() -> ! {
     loop {
         unsafe {
             asm!("hlt");
@@ -1,3 +1,4 @@
+use crate::cpu::without_interrupts;
 use crate::mem::heap::metadata::HeapMetadata;
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
@@ -12,11 +13,14 @@ pub const SEGMENT_SIZE: usize = 0x8;
 #[global_allocator]
 pub static KERNEL_HEAP: GroveHeap = GroveHeap;
 
+/// The heap metadata isn't locked, so every call runs with interrupts disabled. Interrupt handlers (like the timer
+/// wheel's) allocate and free too, and would otherwise corrupt the metadata when they land in the middle of a call.
 pub struct GroveHeap;
 
 unsafe impl GlobalAlloc for GroveHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let allocation = unsafe { HeapMetadata::kernel() }.allocate(layout.size());
+        let allocation =
+            without_interrupts(|| unsafe { HeapMetadata::kernel() }.allocate(layout.size()));
 
         if let Some(allocation) = allocation {
             allocation.as_mut_ptr()
@@ -26,12 +30,13 @@ unsafe impl GlobalAlloc for GroveHeap {
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, _: Layout) {
-        unsafe { HeapMetadata::kernel() }
-            .deallocate(NonNull::new(ptr).expect("Cannot deallocate null pointer!"));
+        let ptr = NonNull::new(ptr).expect("Cannot deallocate null pointer!");
+        without_interrupts(|| unsafe { HeapMetadata::kernel() }.deallocate(ptr));
     }
 
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        let allocation = unsafe { HeapMetadata::kernel() }.allocate(layout.size());
+        let allocation =
+            without_interrupts(|| unsafe { HeapMetadata::kernel() }.allocate(layout.size()));
 
         if let Some(allocation) = allocation {
             allocation.fill(0);
@@ -42,10 +47,9 @@ unsafe impl GlobalAlloc for GroveHeap {
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        let allocation = unsafe { HeapMetadata::kernel() }.reallocate(
-            NonNull::new(ptr).expect("Cannot reallocate null ptr!"),
-            new_size,
-        );
+        let ptr = NonNull::new(ptr).expect("Cannot reallocate null ptr!");
+        let allocation =
+            without_interrupts(|| unsafe { HeapMetadata::kernel() }.reallocate(ptr, new_size));
 
         if let Some(allocation) = allocation {
             allocation.as_mut_ptr()
@@ -1,2 +1,55 @@
+use crate::mem::heap::PAGE_SIZE;
+use crate::mem::page::{PhysAddr, VirtAddr};
+use boot_protocol::UEFIBootInfo;
+use core::ops::Range;
+
 pub mod heap;
 pub mod page;
+
+/// Start of the loader's direct mapping of all physical memory
+static mut HHDM_OFFSET: VirtAddr = 0;
+/// How far above its link address the kernel is running
+static mut KERNEL_SLIDE: u64 = 0;
+/// The stack the loader switched to before calling `_start`
+static mut BOOT_STACK: Range<VirtAddr> = 0..0;
+
+/// Has to run before anything uses [`phys_to_virt`], which is at the very start of `_start`
+pub fn init(boot_info: &UEFIBootInfo) {
+    unsafe {
+        HHDM_OFFSET = boot_info.hhdm_offset;
+        KERNEL_SLIDE = boot_info.kernel_slide;
+        BOOT_STACK = boot_info.stack_bottom..boot_info.stack_top;
+    }
+}
+
+/// The address `addr` can be accessed at through the direct map
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    unsafe { HHDM_OFFSET + addr }
+}
+
+/// The physical address behind `addr`, which has to be a direct map address
+pub fn virt_to_phys(addr: VirtAddr) -> PhysAddr {
+    unsafe { addr - HHDM_OFFSET }
+}
+
+pub fn kernel_slide() -> u64 {
+    unsafe { KERNEL_SLIDE }
+}
+
+/// The link time address of a kernel address, which is what symbol tables and `addr2line` expect
+pub fn unslide(addr: VirtAddr) -> VirtAddr {
+    addr - kernel_slide()
+}
+
+pub fn boot_stack() -> Range<VirtAddr> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        BOOT_STACK.clone()
+    }
+}
+
+/// Whether `addr` lies in the unmapped page right below the boot stack, which means the stack overflowed
+pub fn is_stack_guard(addr: VirtAddr) -> bool {
+    let stack = boot_stack();
+    !stack.is_empty() && (stack.start - PAGE_SIZE as VirtAddr..stack.start).contains(&addr)
+}
@@ -1,10 +1,8 @@
 use crate::mem::heap::PAGE_SIZE;
 use crate::mem::page::page_table::{PageTable, PageTableEntry, PAGE_LEAKED, WRITABLE};
 use crate::mem::page::physical::PhysicalPageAllocator;
-use crate::mem::page::{Page, PageAllocationError, PhysAddr, VirtAddr};
-use crate::UEFIBootInfo;
+use crate::mem::page::{Page, PageAllocationError, VirtAddr};
 use alloc::vec::Vec;
-use core::arch::asm;
 use core::num::NonZeroU64;
 use core::ptr::null_mut;
 
@@ -36,8 +34,6 @@ impl PageAllocator {
 
     pub fn install(&mut self) {
         self.pml4.install();
-        self.pml4 =
-            unsafe { (PageTable::PAGE_TABLE_PML4_PAGE as *mut PageTable).as_mut_unchecked() };
 
         unsafe {
             CURRENT_PAGE_ALLOCATOR = self as *mut Self;
@@ -45,10 +41,10 @@ impl PageAllocator {
     }
 
     pub unsafe fn new_uninit() -> Self {
-        let phys = PhysicalPageAllocator::get().alloc().expect("should exist");
+        let pml4 = PageTable::new().expect("should exist");
 
         Self {
-            pml4: unsafe { (phys as *mut PageTable).as_mut_unchecked() },
+            pml4: unsafe { pml4.as_mut_unchecked() },
             virt_ptr: NonZeroU64::new(1).expect("not zero"),
         }
     }
@@ -123,7 +119,7 @@ impl PageAllocator {
     }
 
     pub fn drop(self) {
-        self.pml4.drop()
+        self.pml4.drop();
     }
 
     fn get_next_addr(&self) -> VirtAddr {
@@ -142,68 +138,14 @@ impl PageAllocator {
     }
 }
 
-pub fn init_paging(boot_info: &UEFIBootInfo) {
+pub fn init_paging() {
     #[allow(static_mut_refs)]
     unsafe {
         KERNEL_PAGE_ALLOCATOR = PageAllocator::new_uninit();
-        KERNEL_PAGE_ALLOCATOR
-            .pml4
-            .setup_pml4()
-            .expect("failed to setup kernel page table");
-    }
-
-    let framebuffer_addr = boot_info.framebuffer.addr() as PhysAddr;
-    for i in 0..(boot_info.framebuffer_size * size_of::<u32>()) / PAGE_SIZE {
-        #[allow(static_mut_refs)]
-        unsafe {
-            KERNEL_PAGE_ALLOCATOR
-                .pml4
-                .map_addr(
-                    framebuffer_addr + (i * PAGE_SIZE) as VirtAddr,
-                    framebuffer_addr + (i * PAGE_SIZE) as PhysAddr,
-                    WRITABLE,
-                )
-                .expect("failed to map framebuffer");
-        }
-    }
-
-    unsafe {
-        KERNEL_PAGE_ALLOCATOR.pml4.0[511] = PageTable::current().0[511];
-    }
-
-    let stack_ptr: VirtAddr;
-    unsafe {
-        asm!("mov {0}, rsp", out(reg) stack_ptr);
-    }
-
-    for i in 0..25 {
-        #[allow(static_mut_refs)]
-        unsafe {
-            KERNEL_PAGE_ALLOCATOR
-                .pml4
-                .map_addr(
-                    stack_ptr - (i * PAGE_SIZE) as VirtAddr,
-                    stack_ptr - (i * PAGE_SIZE) as PhysAddr,
-                    WRITABLE,
-                )
-                .expect("failed to map stack");
-        }
-    }
-
-    let addr = boot_info.memory_bitmap.addr() as PhysAddr;
-    for i in 0..((boot_info.memory_bitmap_size / PAGE_SIZE) + 1) {
-        #[allow(static_mut_refs)]
-        unsafe {
-            KERNEL_PAGE_ALLOCATOR
-                .pml4
-                .map_addr(
-                    addr + (i * PAGE_SIZE) as VirtAddr,
-                    addr + (i * PAGE_SIZE) as PhysAddr,
-                    WRITABLE,
-                )
-                .expect("failed to map memory bitmap");
-        }
+        KERNEL_PAGE_ALLOCATOR.pml4.setup_pml4();
     }
 
-    // TODO: map PageTable::current().0[511] into physical page allocator
+    // Everything else the kernel needs from the loader (framebuffer, ACPI tables, boot modules...) is reached
+    // through the direct map, and the stack sits next to the kernel image, both of which setup_pml4 shares with
+    // the kernel page table
 }
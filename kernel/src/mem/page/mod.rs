@@ -1,8 +1,8 @@
 use crate::mem::page::allocator::PageAllocator;
 use crate::mem::page::page_table::{
-    EXECUTE_DISABLE, PAGE_LEAKED, USER_ACCESSIBLE, WRITABLE,
+    EXECUTE_DISABLE, PAGE_LEAKED, PageTable, USER_ACCESSIBLE, WRITABLE,
 };
-use crate::UEFIBootInfo;
+use boot_protocol::UEFIBootInfo;
 use core::ops::Deref;
 use core::ptr::NonNull;
 
@@ -21,13 +21,36 @@ mod physical;
 /// TODO: PageAllocator should have a kernel() and current() method for getting the necessary PageAllocator
 /// TODO: PageAllocator should be designed to be created over and over (because its going to be used per-process
 
+/// The loader's PML4, kept around after the kernel page table is installed so [`reclaim_boot_memory`] can free
+/// the loader's identity mapping
+static mut LOADER_PML4: PhysAddr = 0;
+
 pub fn init_paging(boot_info: &UEFIBootInfo) {
     physical::setup_ppa(boot_info);
-    allocator::init_paging(boot_info);
+    allocator::init_paging();
 
+    unsafe { LOADER_PML4 = PageTable::current_addr() };
     PageAllocator::kernel().install();
 }
 
+/// Returns the boot services and loader memory to the physical page allocator once the kernel page table is
+/// installed, see [`physical::reclaim_boot_memory`], along with the lower half of the loader's page table (its
+/// identity mapping). The higher half is shared with the kernel page table and stays. Returns the number of pages
+/// reclaimed.
+pub fn reclaim_boot_memory(boot_info: &UEFIBootInfo) -> u64 {
+    let mut freed = physical::reclaim_boot_memory(boot_info);
+
+    // SAFETY: the kernel page table is installed, so nothing uses the loader's PML4 anymore
+    unsafe {
+        if LOADER_PML4 != 0 && LOADER_PML4 != PageTable::current_addr() {
+            freed += PageTable::at(LOADER_PML4).drop();
+            LOADER_PML4 = 0;
+        }
+    }
+
+    freed
+}
+
 pub type VirtAddr = u64;
 pub type PhysAddr = u64;
 
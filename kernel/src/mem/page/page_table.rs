@@ -1,5 +1,6 @@
 use crate::mem::page::physical::PhysicalPageAllocator;
 use crate::mem::page::{PageAllocationError, PhysAddr, VirtAddr};
+use crate::mem::{phys_to_virt, virt_to_phys};
 use core::arch::asm;
 
 pub(super) const PRESENT: u64 = 1 << 0;
@@ -7,11 +8,16 @@ pub(super) const WRITABLE: u64 = 1 << 1;
 pub(super) const USER_ACCESSIBLE: u64 = 1 << 2;
 pub(super) const WRITE_THROUGH: u64 = 1 << 3;
 pub(super) const CACHE_DISABLE: u64 = 1 << 4;
+/// Set on PDPT and PD entries that map a 1 GiB or 2 MiB page directly instead of pointing to a table
+pub(super) const HUGE_PAGE: u64 = 1 << 7;
 pub(super) const PAGE_LEAKED: u64 = 1 << 9;
 pub(super) const EXECUTE_DISABLE: u64 = 1 << 63;
 
 const ADDR_SPAN: u64 = 0x000F_FFFF_FFFF_F000;
 
+const HUGE_PAGE_2M: u64 = 0x20_0000;
+const HUGE_PAGE_1G: u64 = 0x4000_0000;
+
 #[repr(transparent)]
 #[derive(Copy, Clone, Default)]
 pub struct PageTableEntry(pub(super) u64);
@@ -52,49 +58,69 @@ impl PageTableEntry {
         self.0 &= !flag;
         self.0 |= flag & if value { !0 } else { 0 };
     }
+
+    /// The address of the next level table, `None` if this entry maps a huge page instead
+    fn table_addr(&self) -> Option<PhysAddr> {
+        if self.has_flag(HUGE_PAGE) {
+            None
+        } else {
+            self.get_addr()
+        }
+    }
+
+    /// The address of the huge page of `size` bytes mapped by this entry. Bit 12 is the PAT bit for huge pages,
+    /// which is why the address is masked to the page size instead of [`ADDR_SPAN`].
+    fn huge_page_addr(&self, size: u64) -> Option<PhysAddr> {
+        self.get_addr().map(|addr| addr & !(size - 1))
+    }
 }
 
 impl PageTable {
-    const PAGE_TABLE_CREATE_PAGE: VirtAddr = 0xFFFF_FDFF_FFFF_C000;
-    pub(super) const PAGE_TABLE_PML4_PAGE: VirtAddr = 0xFFFF_FDFF_FFFF_D000;
-    const PAGE_TABLE_STATIC_PAGE: VirtAddr = 0xFFFF_FDFF_FFFF_E000;
-    const PAGE_TABLE_WORK_PAGE: VirtAddr = 0xFFFF_FDFF_FFFF_F000;
+    /// Index of the first PML4 entry in the higher half, which is shared by every page table
+    const KERNEL_PML4_START: usize = 256;
 
     pub fn new() -> Result<*mut PageTable, PageAllocationError> {
-        let page = PhysicalPageAllocator::get().alloc()?;
-        Ok(page as *mut PageTable)
+        let phys = PhysicalPageAllocator::get().alloc()?;
+
+        let table = Self::table_at(phys);
+        table.0.fill(PageTableEntry(0));
+
+        Ok(table as *mut PageTable)
     }
 
     pub fn install(&self) {
         unsafe {
-            let phys = PageTable::current()
-                .translate(self as *const Self as u64)
-                .expect("should be mapped");
-            asm!("mov cr3, {}", in(reg) phys);
+            asm!("mov cr3, {}", in(reg) virt_to_phys(self as *const Self as VirtAddr));
         }
     }
 
-    pub fn setup_pml4(&mut self) -> Result<(), PageAllocationError> {
-        let (pml4_i, pdpt_i, pd_t, _) = Self::indexes_of(Self::PAGE_TABLE_PML4_PAGE);
+    /// Shares the higher half of the current page table (the direct map and the kernel image) with this one
+    pub fn setup_pml4(&mut self) {
+        let current = PageTable::current();
 
-        let pdpt = Self::map_temp(self.get_or_create(pml4_i)?);
-        let pd = Self::map_temp(pdpt.get_or_create(pdpt_i)?);
-        let pt = pd.get_or_create(pd_t)?;
+        self.0[Self::KERNEL_PML4_START..].copy_from_slice(&current.0[Self::KERNEL_PML4_START..]);
+    }
 
-        self.map_addr(
-            Self::PAGE_TABLE_PML4_PAGE,
-            PageTable::current()
-                .translate(self as *const Self as VirtAddr)
-                .expect("should exist"),
-            WRITABLE,
-        )?;
-        self.map_addr(Self::PAGE_TABLE_STATIC_PAGE, pt, WRITABLE)?;
+    pub fn current() -> &'static mut PageTable {
+        Self::table_at(Self::current_addr())
+    }
 
-        Ok(())
+    /// Physical address of the current PML4
+    pub fn current_addr() -> PhysAddr {
+        let cr3: PhysAddr;
+        unsafe {
+            asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+        }
+
+        cr3 & ADDR_SPAN
     }
 
-    pub fn current() -> &'static mut PageTable {
-        unsafe { (Self::PAGE_TABLE_PML4_PAGE as *mut PageTable).as_mut_unchecked() }
+    /// The page table at physical address `addr`
+    ///
+    /// # Safety
+    /// `addr` has to hold a page table that nothing else is using.
+    pub unsafe fn at(addr: PhysAddr) -> &'static mut PageTable {
+        Self::table_at(addr)
     }
 
     pub fn map_addr(
@@ -105,9 +131,9 @@ impl PageTable {
     ) -> Result<(), PageAllocationError> {
         let (pml4_idx, pdpt_idx, pd_idx, pt_idx) = Self::indexes_of(vaddr);
 
-        let pdpt = Self::map_temp(self.get_or_create(pml4_idx)?);
-        let pd = Self::map_temp(pdpt.get_or_create(pdpt_idx)?);
-        let pt = Self::map_temp(pd.get_or_create(pd_idx)?);
+        let pdpt = Self::table_at(self.get_or_create(pml4_idx)?);
+        let pd = Self::table_at(pdpt.get_or_create(pdpt_idx)?);
+        let pt = Self::table_at(pd.get_or_create(pd_idx)?);
 
         pt.0[pt_idx].map_to_addr(paddr);
         pt.0[pt_idx].set_flag(flags, true);
@@ -117,56 +143,35 @@ impl PageTable {
     }
 
     pub fn unmap_addr(&mut self, vaddr: VirtAddr) {
-        let (pml4_idx, pdpt_idx, pd_idx, pt_idx) = Self::indexes_of(vaddr);
-
-        if let Some(pdpt) = self.0[pml4_idx].get_addr() {
-            let pdpt = Self::map_temp(pdpt);
-            if let Some(pd) = pdpt.0[pdpt_idx].get_addr() {
-                let pd = Self::map_temp(pd);
-                if let Some(pt) = pd.0[pd_idx].get_addr() {
-                    let pt = Self::map_temp(pt);
+        let pt_idx = Self::indexes_of(vaddr).3;
 
-                    pt.0[pt_idx].map_to_addr(0);
-                }
-            }
+        if let Some(pt) = self.page_table_of(vaddr) {
+            pt.0[pt_idx].map_to_addr(0);
         }
 
         Self::invlpg(vaddr);
     }
 
     pub fn is_mapped(&self, vaddr: VirtAddr) -> bool {
-        let (pml4_idx, pdpt_idx, pd_idx, pt_idx) = Self::indexes_of(vaddr);
-
-        if let Some(pdpt) = self.0[pml4_idx].get_addr() {
-            let pdpt = Self::map_temp(pdpt);
-            if let Some(pd) = pdpt.0[pdpt_idx].get_addr() {
-                let pd = Self::map_temp(pd);
-                if let Some(pt) = pd.0[pd_idx].get_addr() {
-                    let pt = Self::map_temp(pt);
-
-                    pt.0[pt_idx].get_addr().is_some()
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        } else {
-            false
-        }
+        self.translate(vaddr).is_some()
     }
 
     pub fn translate(&self, vaddr: VirtAddr) -> Option<PhysAddr> {
         let (pml4_idx, pdpt_idx, pd_idx, pt_idx) = Self::indexes_of(vaddr);
 
-        let pdpt = self.0[pml4_idx].get_addr()?;
-        let pdpt = Self::map_temp(pdpt);
+        let pdpt = Self::table_at(self.0[pml4_idx].get_addr()?);
 
-        let pd = pdpt.0[pdpt_idx].get_addr()?;
-        let pd = Self::map_temp(pd);
+        let pdpt_entry = pdpt.0[pdpt_idx];
+        if pdpt_entry.has_flag(HUGE_PAGE) {
+            return Some(pdpt_entry.huge_page_addr(HUGE_PAGE_1G)? + (vaddr & (HUGE_PAGE_1G - 1)));
+        }
+        let pd = Self::table_at(pdpt_entry.get_addr()?);
 
-        let pt = pd.0[pd_idx].get_addr()?;
-        let pt = Self::map_temp(pt);
+        let pd_entry = pd.0[pd_idx];
+        if pd_entry.has_flag(HUGE_PAGE) {
+            return Some(pd_entry.huge_page_addr(HUGE_PAGE_2M)? + (vaddr & (HUGE_PAGE_2M - 1)));
+        }
+        let pt = Self::table_at(pd_entry.get_addr()?);
 
         let page = pt.0[pt_idx].get_addr()?;
         let offset = vaddr & 0xFFF;
@@ -175,59 +180,51 @@ impl PageTable {
     }
 
     pub fn set_flags(&mut self, vaddr: VirtAddr, flags: u64, value: bool) -> Option<()> {
-        let (pml4_idx, pdpt_idx, pd_idx, pt_idx) = Self::indexes_of(vaddr);
-
-        let pdpt = self.0[pml4_idx].get_addr()?;
-        let pdpt = Self::map_temp(pdpt);
-
-        let pd = pdpt.0[pdpt_idx].get_addr()?;
-        let pd = Self::map_temp(pd);
-
-        let pt = pd.0[pd_idx].get_addr()?;
-        let pt = Self::map_temp(pt);
+        let pt_idx = Self::indexes_of(vaddr).3;
 
-        pt.0[pt_idx].set_flag(flags, value);
+        self.page_table_of(vaddr)?.0[pt_idx].set_flag(flags, value);
+        Self::invlpg(vaddr);
 
         Some(())
     }
 
     pub fn get_flags(&self, vaddr: VirtAddr) -> Option<PageTableEntry> {
-        let (pml4_idx, pdpt_idx, pd_idx, pt_idx) = Self::indexes_of(vaddr);
-
-        let pdpt = Self::map_temp(self.get(pml4_idx)?);
-        let pd = Self::map_temp(pdpt.get(pdpt_idx)?);
-        let pt = Self::map_temp(pd.get(pd_idx)?);
+        let pt_idx = Self::indexes_of(vaddr).3;
 
-        Some(pt.0[pt_idx])
+        Some(self.page_table_of(vaddr)?.0[pt_idx])
     }
 
-    pub fn drop(&mut self) {
+    /// Frees every table of the lower half. The higher half is shared with every other page table, so it's left alone.
+    /// Returns the number of tables freed, including this one.
+    pub fn drop(&mut self) -> u64 {
         let ppa = PhysicalPageAllocator::get();
-
-        let pml4_addr = self
-            .translate(Self::PAGE_TABLE_PML4_PAGE)
-            .expect("should exist");
-
-        for i in 0..512 {
-            if let Some(pdpt_addr) = self.0[i].get_addr() {
-                let mut pdpt = Self::map_temp(pdpt_addr);
-                for j in 0..512 {
-                    if let Some(pd_addr) = pdpt.0[j].get_addr() {
-                        let pd = Self::map_temp(pd_addr);
-                        for k in 0..512 {
-                            if let Some(pt) = pd.0[k].get_addr() {
-                                ppa.dealloc(pt).expect("should exist")
+        let mut freed = 1;
+
+        for pml4_entry in &self.0[..Self::KERNEL_PML4_START] {
+            if let Some(pdpt_addr) = pml4_entry.get_addr() {
+                let pdpt = Self::table_at(pdpt_addr);
+                for pdpt_entry in pdpt.0.iter().filter(|entry| !entry.has_flag(HUGE_PAGE)) {
+                    if let Some(pd_addr) = pdpt_entry.get_addr() {
+                        let pd = Self::table_at(pd_addr);
+                        for pd_entry in pd.0.iter().filter(|entry| !entry.has_flag(HUGE_PAGE)) {
+                            if let Some(pt) = pd_entry.get_addr() {
+                                ppa.dealloc(pt).expect("should exist");
+                                freed += 1;
                             }
                         }
                         ppa.dealloc(pd_addr).expect("should exist");
-                        pdpt = Self::map_temp(pdpt_addr);
+                        freed += 1;
                     }
                 }
                 ppa.dealloc(pdpt_addr).expect("should exist");
+                freed += 1;
             }
         }
 
-        ppa.dealloc(pml4_addr).expect("should exist");
+        ppa.dealloc(virt_to_phys(self as *const Self as VirtAddr))
+            .expect("should exist");
+
+        freed
     }
 
     fn get_or_create(&mut self, idx: usize) -> Result<PhysAddr, PageAllocationError> {
@@ -236,7 +233,7 @@ impl PageTable {
         } else {
             let phys = PhysicalPageAllocator::get().alloc()?;
 
-            let table = Self::map_create(phys);
+            let table = Self::table_at(phys);
             table.0.fill(PageTableEntry(0));
 
             self.0[idx].map_to_addr(phys);
@@ -265,28 +262,19 @@ impl PageTable {
         )
     }
 
-    fn map_temp(addr: PhysAddr) -> &'static mut PageTable {
-        let work_entry =
-            unsafe { (Self::PAGE_TABLE_STATIC_PAGE as *mut PageTable).as_mut_unchecked() };
-        work_entry.0[511].map_to_addr(addr);
-        work_entry.0[511].set_flag(WRITABLE, true);
+    /// The last level table mapping `vaddr`, `None` if there is none (or `vaddr` is part of a huge page)
+    fn page_table_of(&self, vaddr: VirtAddr) -> Option<&'static mut PageTable> {
+        let (pml4_idx, pdpt_idx, pd_idx, _) = Self::indexes_of(vaddr);
 
-        Self::invlpg(Self::PAGE_TABLE_WORK_PAGE);
+        let pdpt = Self::table_at(self.0[pml4_idx].get_addr()?);
+        let pd = Self::table_at(pdpt.0[pdpt_idx].table_addr()?);
 
-        unsafe { (Self::PAGE_TABLE_WORK_PAGE as *mut PageTable).as_mut_unchecked() }
+        Some(Self::table_at(pd.0[pd_idx].table_addr()?))
     }
 
-    fn map_create(addr: PhysAddr) -> &'static mut PageTable {
-        let pt_idx = Self::indexes_of(Self::PAGE_TABLE_CREATE_PAGE).3;
-
-        let work_entry =
-            unsafe { (Self::PAGE_TABLE_STATIC_PAGE as *mut PageTable).as_mut_unchecked() };
-        work_entry.0[pt_idx].map_to_addr(addr);
-        work_entry.0[pt_idx].set_flag(WRITABLE, true);
-
-        Self::invlpg(Self::PAGE_TABLE_CREATE_PAGE);
-
-        unsafe { (Self::PAGE_TABLE_CREATE_PAGE as *mut PageTable).as_mut_unchecked() }
+    /// Page tables are accessed through the direct map
+    fn table_at(addr: PhysAddr) -> &'static mut PageTable {
+        unsafe { (phys_to_virt(addr) as *mut PageTable).as_mut_unchecked() }
     }
 
     #[inline(always)]
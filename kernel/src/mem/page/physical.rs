@@ -1,6 +1,7 @@
 use crate::mem::heap::PAGE_SIZE;
 use crate::mem::page::{PageAllocationError, PhysAddr};
-use crate::UEFIBootInfo;
+use crate::mem::phys_to_virt;
+use boot_protocol::{MemoryRegion, MemoryRegionKind, UEFIBootInfo};
 
 static mut INSTANCE: PhysicalPageAllocator = PhysicalPageAllocator {
     bitmap: &mut [],
@@ -25,6 +26,10 @@ impl PhysicalPageAllocator {
         Self::idx_to_addr(self.phys_ptr)
     }
 
+    fn page_count(&self) -> usize {
+        self.bitmap.len() * 8
+    }
+
     pub fn get() -> &'static mut PhysicalPageAllocator {
         #[allow(static_mut_refs)]
         unsafe {
@@ -33,15 +38,16 @@ impl PhysicalPageAllocator {
     }
 
     pub fn alloc(&mut self) -> Result<PhysAddr, PageAllocationError> {
-        if self.is_free(self.addr()) {
+        if self.phys_ptr < self.page_count() && self.is_free(self.addr()) {
             let addr = Self::idx_to_addr(self.phys_ptr);
             self.set_used(addr, true);
             self.phys_ptr += 1;
             Ok(addr)
         } else {
-            for idx in self.phys_ptr..(self.bitmap.len() * 8) {
+            for idx in self.phys_ptr..self.page_count() {
                 if self.is_free(Self::idx_to_addr(idx)) {
                     let addr = Self::idx_to_addr(idx);
+                    self.set_used(addr, true);
                     self.phys_ptr = idx + 1;
                     return Ok(addr);
                 }
@@ -54,7 +60,7 @@ impl PhysicalPageAllocator {
     pub fn dealloc(&mut self, addr: PhysAddr) -> Result<(), PageAllocationError> {
         let idx = Self::addr_to_idx(addr);
 
-        if idx > self.bitmap.len() * 8 {
+        if idx >= self.page_count() {
             Err(PageAllocationError::InvalidDeallocationPointer)
         } else {
             self.set_used(addr, false);
@@ -74,6 +80,28 @@ impl PhysicalPageAllocator {
         self.bitmap[idx] & (1 << offset) == 0
     }
 
+    fn set_range_used(&mut self, start: PhysAddr, page_count: u64, used: bool) {
+        for i in 0..page_count {
+            self.set_used(start + i * PAGE_SIZE as PhysAddr, used);
+        }
+    }
+
+    /// Frees the pages in the range that are still in use, returning how many that were. Page 0 is always skipped,
+    /// like in [`setup_ppa`].
+    fn free_range(&mut self, start: PhysAddr, page_count: u64) -> u64 {
+        let mut freed = 0;
+        for i in 0..page_count {
+            let addr = start + i * PAGE_SIZE as PhysAddr;
+            if addr != 0 && !self.is_free(addr) {
+                self.dealloc(addr)
+                    .expect("the memory map is covered by the bitmap");
+                freed += 1;
+            }
+        }
+
+        freed
+    }
+
     fn set_used(&mut self, addr: PhysAddr, used: bool) {
         let idx = Self::addr_to_idx(addr);
         let offset = idx % 8;
@@ -86,18 +114,86 @@ impl PhysicalPageAllocator {
     }
 }
 
+/// Builds the physical page allocator from the memory map passed in by the loader.
+///
+/// Every page starts out as used and only `Usable` regions are freed, so firmware, loader and kernel memory is
+/// never handed out. The bitmap itself is placed in the first usable region that is big enough to hold it.
 pub fn setup_ppa(boot_info: &UEFIBootInfo) {
+    // SAFETY: the loader's identity mapping is still active, so the memory map is still readable
+    let regions = unsafe { boot_info.memory_map() };
+
+    let page_count = regions
+        .iter()
+        .filter(|region| {
+            !matches!(
+                region.kind,
+                MemoryRegionKind::Mmio | MemoryRegionKind::Reserved
+            )
+        })
+        .map(|region| PhysicalPageAllocator::addr_to_idx(region.end()))
+        .max()
+        .expect("memory map should not be empty");
+
+    let bitmap_size = page_count.div_ceil(8);
+    let bitmap_pages = bitmap_size.div_ceil(PAGE_SIZE) as u64;
+
+    let bitmap_region = regions
+        .iter()
+        .find(|region| {
+            region.kind == MemoryRegionKind::Usable
+                && region.start != 0
+                && region.page_count >= bitmap_pages
+        })
+        .expect("no usable memory region is large enough for the physical page bitmap");
+
     unsafe {
-        INSTANCE.bitmap =
-            core::slice::from_raw_parts_mut(boot_info.memory_bitmap, boot_info.memory_bitmap_size);
+        INSTANCE.bitmap = core::slice::from_raw_parts_mut(
+            phys_to_virt(bitmap_region.start) as *mut u8,
+            bitmap_size,
+        );
         INSTANCE.phys_ptr = 0;
     }
 
-    let addr = boot_info.memory_bitmap.addr() as PhysAddr;
-    for i in 0..((boot_info.memory_bitmap_size / PAGE_SIZE) + 1) {
-        #[allow(static_mut_refs)]
-        unsafe {
-            INSTANCE.set_used(addr + (i * PAGE_SIZE) as PhysAddr, true);
-        }
+    let ppa = PhysicalPageAllocator::get();
+    ppa.bitmap.fill(0xFF);
+
+    for region in regions
+        .iter()
+        .filter(|region| region.kind == MemoryRegionKind::Usable)
+    {
+        ppa.set_range_used(region.start, region.page_count, false);
     }
+
+    // Page 0 is kept reserved so a physical address of 0 can never be handed out
+    ppa.set_used(0, true);
+    ppa.set_range_used(bitmap_region.start, bitmap_pages, true);
+}
+
+/// Hands the boot services and loader memory to the physical page allocator, returning how many pages that freed.
+///
+/// Has to run after the kernel page table is installed and everything the kernel needs from the boot info has been
+/// copied out of loader memory. The loader's page tables, the kernel image and stack, boot modules and runtime
+/// services memory all have their own kinds, so none of them are touched here. The loader's page tables are freed
+/// by walking them instead, see [`super::reclaim_boot_memory`].
+pub fn reclaim_boot_memory(boot_info: &UEFIBootInfo) -> u64 {
+    // The memory map is itself loader memory and the identity mapping is gone by now, so it's read through the
+    // direct map. Freeing its pages is fine, nothing allocates until this returns.
+    let regions = unsafe {
+        core::slice::from_raw_parts(
+            phys_to_virt(boot_info.memory_map as PhysAddr) as *const MemoryRegion,
+            boot_info.memory_map_len,
+        )
+    };
+
+    let ppa = PhysicalPageAllocator::get();
+    regions
+        .iter()
+        .filter(|region| {
+            matches!(
+                region.kind,
+                MemoryRegionKind::BootServices | MemoryRegionKind::LoaderData
+            )
+        })
+        .map(|region| ppa.free_range(region.start, region.page_count))
+        .sum()
 }
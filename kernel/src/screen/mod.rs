@@ -1,19 +1,25 @@
-use crate::UEFIBootInfo;
+use crate::mem::page::PhysAddr;
+use crate::mem::phys_to_virt;
 use crate::screen::font::{KERNEL_FONT, PSFFont};
+use boot_protocol::{Framebuffer, UEFIBootInfo};
 use core::fmt::Write;
 
 mod font;
 
 pub struct FramebufferWriter {
     framebuffer: &'static mut [u32],
+    /// Layout of the framebuffer, used to turn colors into pixel values
+    info: Framebuffer,
     width: usize,
     height: usize,
+    stride: usize,
 
     cursor_x: usize,
     cursor_y: usize,
 
     curr_font: &'static PSFFont,
 
+    /// Colors are `0xRRGGBB`, whatever the pixel format of the framebuffer
     fg_color: u32,
     bg_color: u32,
 }
@@ -23,27 +29,36 @@ impl FramebufferWriter {
         self.cursor_x = 0;
         self.cursor_y = 0;
 
-        self.framebuffer.fill(self.bg_color);
+        let bg = self.info.encode_color(self.bg_color);
+        self.framebuffer.fill(bg);
     }
 }
 
 impl From<&UEFIBootInfo> for FramebufferWriter {
     fn from(value: &UEFIBootInfo) -> Self {
+        // The loader passes the physical address, which stays reachable through the direct map after the
+        // kernel page table is installed
+        let mut info = value.framebuffer;
+        if !info.base.is_null() {
+            info.base = phys_to_virt(info.base as PhysAddr) as *mut u32;
+        }
+
         // SAFETY: this is okay because we know the base framebuffer pointer and the framebuffer size
-        let framebuffer =
-            unsafe { core::slice::from_raw_parts_mut(value.framebuffer, value.framebuffer_size) };
+        let framebuffer = unsafe { info.as_slice() };
 
         Self {
             framebuffer,
-            width: value.framebuffer_width,
-            height: value.framebuffer_height,
+            info,
+            width: value.framebuffer.width,
+            height: value.framebuffer.height,
+            stride: value.framebuffer.stride,
 
             cursor_x: 0,
             cursor_y: 0,
             curr_font: &KERNEL_FONT,
 
-            fg_color: 0xFFFFFFFF,
-            bg_color: 0x00000000,
+            fg_color: 0xFFFFFF,
+            bg_color: 0x000000,
         }
     }
 }
@@ -70,6 +85,9 @@ impl Write for FramebufferWriter {
 
         let bytes_per_row = (self.curr_font.width as usize + 7) / 8;
 
+        let fg = self.info.encode_color(self.fg_color);
+        let bg = self.info.encode_color(self.bg_color);
+
         for row in 0..glyph_height {
             let row_start = row * bytes_per_row;
 
@@ -88,12 +106,8 @@ impl Write for FramebufferWriter {
                 let y = self.cursor_y + row;
 
                 if x < self.width && y < self.height {
-                    let pixel_index = y * self.width + x;
-                    self.framebuffer[pixel_index] = if pixel_on != 0 {
-                        self.fg_color
-                    } else {
-                        self.bg_color
-                    };
+                    let pixel_index = y * self.stride + x;
+                    self.framebuffer[pixel_index] = if pixel_on != 0 { fg } else { bg };
                 }
             }
         }
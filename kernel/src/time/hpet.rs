@@ -0,0 +1,85 @@
+//! The HPET's main counter, used as a clock and to calibrate the TSC.
+
+use crate::acpi;
+use crate::mem::page::VirtAddr;
+use crate::mem::phys_to_virt;
+
+const REG_CAPABILITIES: u64 = 0x00;
+const REG_CONFIG: u64 = 0x10;
+const REG_MAIN_COUNTER: u64 = 0xF0;
+
+const CAPABILITY_64_BIT: u64 = 1 << 13;
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+/// Direct map address of the HPET's registers, 0 if there's no HPET
+static mut BASE: VirtAddr = 0;
+/// Length of a counter tick in femtoseconds
+static mut PERIOD_FS: u64 = 0;
+static mut WIDE_COUNTER: bool = false;
+
+/// Finds the HPET through ACPI and starts its main counter. Returns whether there is one.
+pub fn init() -> bool {
+    let Some(hpet) = acpi::hpet() else {
+        return false;
+    };
+
+    unsafe {
+        BASE = phys_to_virt(hpet.base_address());
+
+        let capabilities = read(REG_CAPABILITIES);
+        PERIOD_FS = capabilities >> 32;
+        WIDE_COUNTER = capabilities & CAPABILITY_64_BIT != 0;
+
+        write(REG_CONFIG, read(REG_CONFIG) | CONFIG_ENABLE);
+    }
+
+    true
+}
+
+pub fn is_available() -> bool {
+    unsafe { BASE != 0 && PERIOD_FS != 0 }
+}
+
+/// The main counter, a 32-bit counter wraps around after a few minutes
+pub fn counter() -> u64 {
+    unsafe { read(REG_MAIN_COUNTER) }
+}
+
+pub fn is_64_bit() -> bool {
+    unsafe { WIDE_COUNTER }
+}
+
+pub fn frequency() -> u64 {
+    1_000_000_000_000_000 / unsafe { PERIOD_FS }
+}
+
+/// Converts a number of counter ticks to nanoseconds
+pub fn ticks_to_nanos(ticks: u64) -> u64 {
+    (ticks as u128 * unsafe { PERIOD_FS } as u128 / FEMTOS_PER_NANO as u128) as u64
+}
+
+/// Busy waits for `nanos` nanoseconds
+pub fn spin_nanos(nanos: u64) {
+    let ticks = (nanos as u128 * FEMTOS_PER_NANO as u128 / unsafe { PERIOD_FS } as u128) as u64;
+    let start = counter();
+
+    // Wrapping arithmetic keeps this working across a 32-bit counter wrapping around
+    let mask = if is_64_bit() {
+        u64::MAX
+    } else {
+        u32::MAX as u64
+    };
+    while counter().wrapping_sub(start) & mask < ticks {
+        core::hint::spin_loop();
+    }
+}
+
+unsafe fn read(register: u64) -> u64 {
+    unsafe { ((BASE + register) as *const u64).read_volatile() }
+}
+
+unsafe fn write(register: u64, value: u64) {
+    unsafe { ((BASE + register) as *mut u64).write_volatile(value) }
+}
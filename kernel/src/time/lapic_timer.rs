@@ -0,0 +1,90 @@
+//! The local APIC timer, in periodic, one-shot and TSC-deadline mode.
+
+use crate::cpu::apic::{self, LVT_MASKED};
+use crate::cpu::wrmsr;
+use crate::time::{self, ClockSource, Duration, Instant, tsc};
+use core::arch::asm;
+
+const DIVIDE_BY_16: u32 = 0b0011;
+
+const MODE_ONE_SHOT: u32 = 0b00 << 17;
+const MODE_PERIODIC: u32 = 0b01 << 17;
+const MODE_TSC_DEADLINE: u32 = 0b10 << 17;
+
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+
+const CALIBRATION: Duration = Duration::from_millis(10);
+
+/// Timer ticks per millisecond with the divider at 16, 0 until calibrated
+static mut TICKS_PER_MS: u64 = 0;
+
+/// Measures the timer's rate against the kernel clock, which has to be running already
+pub fn calibrate() {
+    apic::write(apic::REG_TIMER_DIVIDE, DIVIDE_BY_16);
+    apic::write(apic::REG_LVT_TIMER, LVT_MASKED | MODE_ONE_SHOT);
+    apic::write(apic::REG_TIMER_INITIAL_COUNT, u32::MAX);
+
+    time::spin(CALIBRATION);
+
+    let elapsed = u32::MAX - apic::read(apic::REG_TIMER_CURRENT_COUNT);
+    stop();
+
+    unsafe { TICKS_PER_MS = elapsed as u64 / CALIBRATION.as_millis() as u64 };
+}
+
+pub fn ticks_per_ms() -> u64 {
+    unsafe { TICKS_PER_MS }
+}
+
+/// Whether [`set_deadline`] uses TSC-deadline mode, which needs the TSC to be the kernel clock
+pub fn uses_tsc_deadline() -> bool {
+    tsc::supports_deadline() && time::clock_source() == Some(ClockSource::Tsc)
+}
+
+/// Raises `vector` every `period`
+pub fn start_periodic(period: Duration, vector: u8) {
+    apic::write(apic::REG_TIMER_DIVIDE, DIVIDE_BY_16);
+    apic::write(apic::REG_LVT_TIMER, MODE_PERIODIC | vector as u32);
+    apic::write(apic::REG_TIMER_INITIAL_COUNT, ticks_for(period));
+}
+
+/// Raises `vector` once after `delay`
+pub fn start_one_shot(delay: Duration, vector: u8) {
+    apic::write(apic::REG_TIMER_DIVIDE, DIVIDE_BY_16);
+    apic::write(apic::REG_LVT_TIMER, MODE_ONE_SHOT | vector as u32);
+    apic::write(apic::REG_TIMER_INITIAL_COUNT, ticks_for(delay));
+}
+
+/// Raises `vector` once at `deadline`, using TSC-deadline mode when possible and a one-shot timer otherwise
+pub fn set_deadline(deadline: Instant, vector: u8) {
+    if !uses_tsc_deadline() {
+        start_one_shot(deadline.saturating_duration_since(Instant::now()), vector);
+        return;
+    }
+
+    apic::write(apic::REG_LVT_TIMER, MODE_TSC_DEADLINE | vector as u32);
+
+    // The LVT write has to land before the deadline is armed, which an MMIO write followed by a WRMSR doesn't
+    // guarantee on its own
+    unsafe {
+        asm!("mfence", options(nostack, preserves_flags));
+        wrmsr(
+            IA32_TSC_DEADLINE,
+            time::boot_count() + tsc::nanos_to_cycles(deadline.nanos_since_boot()),
+        );
+    }
+}
+
+pub fn stop() {
+    apic::write(apic::REG_LVT_TIMER, LVT_MASKED);
+    apic::write(apic::REG_TIMER_INITIAL_COUNT, 0);
+    if tsc::supports_deadline() {
+        unsafe { wrmsr(IA32_TSC_DEADLINE, 0) };
+    }
+}
+
+/// The initial count for `duration`, at least 1 since a count of 0 stops the timer
+fn ticks_for(duration: Duration) -> u32 {
+    let ticks = duration.as_nanos() * ticks_per_ms() as u128 / 1_000_000;
+    ticks.clamp(1, u32::MAX as u128) as u32
+}
@@ -0,0 +1,188 @@
+//! Kernel timekeeping: a monotonic clock, [`Instant`], [`sleep`] and the hardware behind them.
+//!
+//! The clock is the TSC when it's invariant (or there's nothing else), and the HPET's main counter otherwise. A
+//! 32-bit HPET wraps after a few minutes, so it's never used as the clock. The TSC is calibrated against the HPET, or
+//! the PIT on machines without one.
+//!
+//! Wall-clock time is in [`system_time`], seeded from the CMOS RTC.
+
+use crate::cpu::rdtsc;
+use crate::println;
+use core::ops::{Add, Sub};
+
+pub use core::time::Duration;
+
+pub mod hpet;
+pub mod lapic_timer;
+pub mod pit;
+pub mod rtc;
+pub mod system_time;
+pub mod tsc;
+pub mod wheel;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClockSource {
+    Tsc,
+    Hpet,
+}
+
+static mut CLOCK_SOURCE: Option<ClockSource> = None;
+static mut CALIBRATION_SOURCE: Option<tsc::CalibrationSource> = None;
+/// The clock source's count when the clock was started, which is what [`Instant`]s are relative to
+static mut BOOT_COUNT: u64 = 0;
+
+/// Calibrates the TSC and the LAPIC timer and starts the clock. The local APIC has to be initialized already.
+pub fn init() {
+    hpet::init();
+    let calibration = tsc::calibrate();
+
+    let source = if tsc::is_invariant() || !hpet::is_available() || !hpet::is_64_bit() {
+        ClockSource::Tsc
+    } else {
+        ClockSource::Hpet
+    };
+
+    unsafe {
+        CALIBRATION_SOURCE = Some(calibration);
+        BOOT_COUNT = match source {
+            ClockSource::Tsc => rdtsc(),
+            ClockSource::Hpet => hpet::counter(),
+        };
+        CLOCK_SOURCE = Some(source);
+    }
+
+    lapic_timer::calibrate();
+}
+
+pub fn clock_source() -> Option<ClockSource> {
+    unsafe { CLOCK_SOURCE }
+}
+
+/// The clock source's count at the start of the clock
+pub fn boot_count() -> u64 {
+    unsafe { BOOT_COUNT }
+}
+
+/// Nanoseconds since [`init`], 0 before it
+pub fn nanos() -> u64 {
+    match clock_source() {
+        Some(ClockSource::Tsc) => tsc::cycles_to_nanos(rdtsc().saturating_sub(boot_count())),
+        Some(ClockSource::Hpet) => hpet::ticks_to_nanos(hpet::counter().wrapping_sub(boot_count())),
+        None => 0,
+    }
+}
+
+/// A point on the monotonic clock
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(nanos())
+    }
+
+    pub const fn from_nanos_since_boot(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    pub const fn nanos_since_boot(&self) -> u64 {
+        self.0
+    }
+
+    /// The time from `earlier` to `self`, 0 if `earlier` is later
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().saturating_duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|nanos| self.0.checked_add(nanos))
+            .map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).unwrap_or(Instant(u64::MAX))
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        Instant(
+            self.0
+                .saturating_sub(u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)),
+        )
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+}
+
+/// Busy waits for `duration`
+pub fn spin(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Waits for `duration`, halting between timer ticks once they're running and spinning before that
+pub fn sleep(duration: Duration) {
+    if !wheel::is_running() {
+        return spin(duration);
+    }
+
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        unsafe { core::arch::asm!("hlt", options(nomem, nostack)) };
+    }
+}
+
+pub fn print_time_info() {
+    let Some(source) = clock_source() else {
+        return;
+    };
+
+    println!(
+        "Clock: {:?}, TSC @ {} MHz ({}invariant, calibrated against the {:?})",
+        source,
+        tsc::frequency() / 1_000_000,
+        if tsc::is_invariant() { "" } else { "not " },
+        unsafe { CALIBRATION_SOURCE }.unwrap_or(tsc::CalibrationSource::Pit)
+    );
+    if hpet::is_available() {
+        println!(
+            "HPET @ {} kHz ({}-bit)",
+            hpet::frequency() / 1000,
+            if hpet::is_64_bit() { 64 } else { 32 }
+        );
+    }
+    println!(
+        "LAPIC timer: {} ticks/ms, {}",
+        lapic_timer::ticks_per_ms(),
+        if lapic_timer::uses_tsc_deadline() {
+            "TSC-deadline"
+        } else {
+            "one-shot deadlines"
+        }
+    );
+}
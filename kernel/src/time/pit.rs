@@ -0,0 +1,42 @@
+//! The 8254 PIT, only used to calibrate the TSC on machines without an HPET.
+//!
+//! Channel 2 is used since its gate can be controlled through port 0x61 and its output read back there, without
+//! needing an interrupt.
+
+use crate::cpu::{inb, outb};
+
+/// The PIT's input clock in Hz
+pub const FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_2_DATA: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Controls the channel 2 gate (bit 0) and the speaker (bit 1), bit 5 reads back the channel 2 output
+const SPEAKER_CONTROL: u16 = 0x61;
+
+const GATE: u8 = 1 << 0;
+const SPEAKER: u8 = 1 << 1;
+const OUTPUT: u8 = 1 << 5;
+
+/// Busy waits for `micros` microseconds, at most about 54 ms since the counter is 16 bits wide
+pub fn spin_micros(micros: u64) {
+    let count = (FREQUENCY * micros / 1_000_000).clamp(1, u16::MAX as u64) as u16;
+
+    unsafe {
+        // Gate low while the count is loaded, with the speaker disconnected
+        let control = inb(SPEAKER_CONTROL) & !(GATE | SPEAKER);
+        outb(SPEAKER_CONTROL, control);
+
+        // Channel 2, low byte then high byte, mode 0 (interrupt on terminal count), binary
+        outb(COMMAND, 0b1011_0000);
+        outb(CHANNEL_2_DATA, count as u8);
+        outb(CHANNEL_2_DATA, (count >> 8) as u8);
+
+        // Raising the gate starts the countdown, the output goes high once it reaches 0
+        outb(SPEAKER_CONTROL, control | GATE);
+        while inb(SPEAKER_CONTROL) & OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+
+        outb(SPEAKER_CONTROL, control);
+    }
+}
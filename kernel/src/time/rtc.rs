@@ -0,0 +1,133 @@
+//! The CMOS real-time clock, read once at boot to seed [`SystemTime`](crate::time::system_time::SystemTime).
+
+use crate::acpi;
+use crate::cpu::{inb, outb, without_interrupts};
+use crate::time::system_time::DateTime;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Set in the hours register for PM times in 12 hour mode
+const HOURS_PM: u8 = 1 << 7;
+
+/// How often the registers are read before giving up on getting the same values twice in a row
+const MAX_ATTEMPTS: usize = 16;
+
+#[derive(Debug)]
+pub enum RtcError {
+    /// The registers kept changing between reads
+    Unstable,
+    /// The RTC holds something that isn't a valid date and time
+    InvalidTime,
+}
+
+/// The raw register values, compared between reads to catch an update happening halfway through
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Registers {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+/// Reads the current date and time. The RTC doesn't say which time zone it's in, it's usually UTC but some systems
+/// keep it in local time.
+pub fn read() -> Result<DateTime, RtcError> {
+    let century_register = acpi::fadt().and_then(|fadt| fadt.century_register());
+
+    let mut previous = None;
+    for _ in 0..MAX_ATTEMPTS {
+        let registers = without_interrupts(|| read_registers(century_register));
+        if previous == Some(registers) {
+            return decode(registers, century_register.is_some());
+        }
+        previous = Some(registers);
+    }
+
+    Err(RtcError::Unstable)
+}
+
+fn read_registers(century_register: Option<u8>) -> Registers {
+    // An update takes under 2 ms once the flag is set, reading during one can return a mix of old and new values
+    while read_cmos(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    Registers {
+        second: read_cmos(REG_SECONDS),
+        minute: read_cmos(REG_MINUTES),
+        hour: read_cmos(REG_HOURS),
+        day: read_cmos(REG_DAY),
+        month: read_cmos(REG_MONTH),
+        year: read_cmos(REG_YEAR),
+        century: century_register.map_or(0, read_cmos),
+    }
+}
+
+fn decode(registers: Registers, has_century: bool) -> Result<DateTime, RtcError> {
+    let status_b = read_cmos(REG_STATUS_B);
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let convert = |value: u8| {
+        if binary {
+            value
+        } else {
+            (value >> 4) * 10 + (value & 0xF)
+        }
+    };
+
+    // The PM bit isn't part of the BCD value
+    let pm = registers.hour & HOURS_PM != 0;
+    let mut hour = convert(registers.hour & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight and 12 PM is noon
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    let year = convert(registers.year) as u16;
+    // Without a century register, the RTC is assumed to be somewhere in 2000-2099
+    let century = if has_century {
+        convert(registers.century) as u16
+    } else {
+        20
+    };
+
+    let date_time = DateTime {
+        year: century * 100 + year,
+        month: convert(registers.month),
+        day: convert(registers.day),
+        hour,
+        minute: convert(registers.minute),
+        second: convert(registers.second),
+    };
+
+    if !date_time.is_valid() {
+        return Err(RtcError::InvalidTime);
+    }
+
+    Ok(date_time)
+}
+
+fn read_cmos(register: u8) -> u8 {
+    unsafe {
+        outb(CMOS_INDEX, register);
+        inb(CMOS_DATA)
+    }
+}
@@ -0,0 +1,152 @@
+//! Wall-clock time, seeded from the RTC at boot and kept going by the monotonic clock.
+
+use crate::time::rtc::{self, RtcError};
+use crate::time::{Duration, Instant};
+use core::fmt::{Display, Formatter};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// A calendar date and time
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Seconds since the Unix epoch
+    pub fn to_unix(&self) -> u64 {
+        // Days from civil, counting years from March so the leap day is at the end of the year
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = self.month as i64;
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days as u64 * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    pub fn from_unix(seconds: u64) -> Self {
+        let days = (seconds / SECONDS_PER_DAY) as i64 + 719_468;
+        let seconds_of_day = seconds % SECONDS_PER_DAY;
+
+        // The inverse of to_unix
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+        }
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// A point in wall-clock time, as nanoseconds since the Unix epoch
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SystemTime(u64);
+
+pub const UNIX_EPOCH: SystemTime = SystemTime(0);
+
+/// The wall-clock time at the instant the clock was seeded
+static mut SEED: Option<(SystemTime, Instant)> = None;
+
+impl SystemTime {
+    /// The current time, which counts up from the epoch if the clock was never seeded
+    pub fn now() -> Self {
+        let (time, instant) =
+            unsafe { SEED }.unwrap_or((UNIX_EPOCH, Instant::from_nanos_since_boot(0)));
+
+        Self(time.0 + instant.elapsed().as_nanos() as u64)
+    }
+
+    pub fn duration_since(&self, earlier: SystemTime) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn from_unix(seconds: u64) -> Self {
+        Self(seconds * 1_000_000_000)
+    }
+
+    pub fn unix_seconds(&self) -> u64 {
+        self.0 / 1_000_000_000
+    }
+
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_unix(self.unix_seconds())
+    }
+}
+
+/// Seeds the wall clock from the RTC, the monotonic clock has to be running already
+pub fn init() -> Result<(), RtcError> {
+    let date_time = rtc::read()?;
+
+    unsafe { SEED = Some((SystemTime::from_unix(date_time.to_unix()), Instant::now())) };
+
+    Ok(())
+}
+
+pub fn is_seeded() -> bool {
+    #[allow(static_mut_refs)]
+    unsafe {
+        SEED.is_some()
+    }
+}
@@ -0,0 +1,69 @@
+//! The time stamp counter, calibrated against the HPET or the PIT.
+
+use crate::cpu::{cpuid, rdtsc};
+use crate::time::{hpet, pit};
+
+/// How long calibration measures for
+const CALIBRATION_MICROS: u64 = 10_000;
+
+/// TSC frequency in Hz, 0 until calibrated
+static mut FREQUENCY: u64 = 0;
+
+#[derive(Copy, Clone, Debug)]
+pub enum CalibrationSource {
+    Hpet,
+    Pit,
+}
+
+/// Measures the TSC frequency against the HPET if there is one, and the PIT otherwise
+pub fn calibrate() -> CalibrationSource {
+    let source = if hpet::is_available() {
+        CalibrationSource::Hpet
+    } else {
+        CalibrationSource::Pit
+    };
+
+    let start = rdtsc();
+    match source {
+        CalibrationSource::Hpet => hpet::spin_nanos(CALIBRATION_MICROS * 1000),
+        CalibrationSource::Pit => pit::spin_micros(CALIBRATION_MICROS),
+    }
+    let cycles = rdtsc() - start;
+
+    unsafe { FREQUENCY = cycles * (1_000_000 / CALIBRATION_MICROS) };
+
+    source
+}
+
+pub fn frequency() -> u64 {
+    unsafe { FREQUENCY }
+}
+
+pub fn is_calibrated() -> bool {
+    frequency() != 0
+}
+
+/// An invariant TSC runs at the same rate in every P-, C- and T-state, which is what makes it usable as a clock
+pub fn is_invariant() -> bool {
+    let (max_extended, _, _, _) = cpuid(0x8000_0000, 0);
+    if max_extended < 0x8000_0007 {
+        return false;
+    }
+
+    let (_, _, _, edx) = cpuid(0x8000_0007, 0);
+    edx & (1 << 8) != 0
+}
+
+/// Whether the LAPIC timer can fire at a TSC value, see [`crate::time::lapic_timer::set_deadline`]
+pub fn supports_deadline() -> bool {
+    let (_, _, ecx, _) = cpuid(1, 0);
+    ecx & (1 << 24) != 0
+}
+
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    (cycles as u128 * 1_000_000_000 / frequency() as u128) as u64
+}
+
+pub fn nanos_to_cycles(nanos: u64) -> u64 {
+    (nanos as u128 * frequency() as u128 / 1_000_000_000) as u64
+}
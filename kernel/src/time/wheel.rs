@@ -0,0 +1,153 @@
+//! A timer wheel for kernel timeouts, driven by a periodic LAPIC timer tick.
+//!
+//! Timers are kept in one of [`SLOTS`] lists by the tick they expire on, so every tick only has to look at a
+//! single list. Callbacks run from the timer interrupt, with interrupts disabled.
+
+use crate::cpu::interrupts::{self, InterruptError};
+use crate::cpu::without_interrupts;
+use crate::time::{Duration, Instant, lapic_timer};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+const SLOTS: usize = 256;
+
+/// How often the wheel is advanced, and so the resolution of timers
+pub const TICK: Duration = Duration::from_millis(1);
+
+pub type TimerCallback = Box<dyn FnOnce() + Send>;
+
+/// Returned by [`add_timer`], used to cancel the timer before it fires
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TimerId {
+    id: u64,
+    slot: usize,
+}
+
+struct Timer {
+    id: u64,
+    /// The tick the timer fires on
+    expires: u64,
+    callback: TimerCallback,
+}
+
+pub struct TimerWheel {
+    slots: [Vec<Timer>; SLOTS],
+    tick_nanos: u64,
+    /// The last tick that was processed
+    current: u64,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    pub const fn new(tick: Duration) -> Self {
+        Self {
+            slots: [const { Vec::new() }; SLOTS],
+            tick_nanos: tick.as_nanos() as u64,
+            current: 0,
+            next_id: 0,
+        }
+    }
+
+    /// Adds a timer firing at `deadline`, which is rounded up to the next tick so timers never fire early
+    pub fn add(&mut self, deadline: Instant, callback: TimerCallback) -> TimerId {
+        let expires = deadline
+            .nanos_since_boot()
+            .div_ceil(self.tick_nanos)
+            .max(self.current + 1);
+        let slot = expires as usize % SLOTS;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.slots[slot].push(Timer {
+            id,
+            expires,
+            callback,
+        });
+
+        TimerId { id, slot }
+    }
+
+    /// Removes a timer that hasn't fired yet, returning whether it was still there
+    pub fn cancel(&mut self, timer: TimerId) -> bool {
+        let slot = &mut self.slots[timer.slot];
+        match slot.iter().position(|entry| entry.id == timer.id) {
+            Some(index) => {
+                slot.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the wheel up to `now`, returning the callbacks of every timer that expired on the way. They're
+    /// returned rather than called, so they can add timers of their own.
+    pub fn advance(&mut self, now: Instant) -> Vec<TimerCallback> {
+        let target = now.nanos_since_boot() / self.tick_nanos;
+        let mut expired = Vec::new();
+        if target <= self.current {
+            return expired;
+        }
+
+        // After a full turn every slot has been looked at, so missed ticks past that don't need a visit of their own
+        let steps = (target - self.current).min(SLOTS as u64);
+        for tick in self.current + 1..=self.current + steps {
+            let slot = &mut self.slots[tick as usize % SLOTS];
+
+            let mut index = 0;
+            while index < slot.len() {
+                if slot[index].expires <= target {
+                    expired.push(slot.swap_remove(index).callback);
+                } else {
+                    index += 1;
+                }
+            }
+        }
+        self.current = target;
+
+        expired
+    }
+}
+
+static mut WHEEL: TimerWheel = TimerWheel::new(TICK);
+/// The vector of the timer tick, 0 until [`start`] is called
+static mut VECTOR: u8 = 0;
+
+/// Starts the periodic timer tick that advances the wheel
+pub fn start() -> Result<(), InterruptError> {
+    let vector = interrupts::allocate_vector()?;
+    interrupts::register(vector, |_: &mut _| {
+        on_tick();
+        true
+    })?;
+
+    unsafe { VECTOR = vector };
+    lapic_timer::start_periodic(TICK, vector);
+
+    Ok(())
+}
+
+pub fn is_running() -> bool {
+    unsafe { VECTOR != 0 }
+}
+
+/// Calls `callback` from the timer interrupt once `delay` has passed. Nothing fires until [`start`] has been called.
+pub fn add_timer(delay: Duration, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    let deadline = Instant::now() + delay;
+
+    #[allow(static_mut_refs)]
+    without_interrupts(|| unsafe { WHEEL.add(deadline, Box::new(callback)) })
+}
+
+pub fn cancel_timer(timer: TimerId) -> bool {
+    #[allow(static_mut_refs)]
+    without_interrupts(|| unsafe { WHEEL.cancel(timer) })
+}
+
+fn on_tick() {
+    #[allow(static_mut_refs)]
+    let expired = unsafe { WHEEL.advance(Instant::now()) };
+
+    for callback in expired {
+        callback();
+    }
+}
@@ -0,0 +1,147 @@
+//! Kernel self-updates through the loader's A/B slots.
+//!
+//! The loader boots the kernel from one of two slots and keeps a [`BootState`] in a UEFI variable. An update is
+//! written into the slot the kernel wasn't booted from and marked pending, after which the loader tries it for a
+//! limited number of boots. The updated kernel has to call [`confirm_boot`] once it's up, otherwise the loader goes
+//! back to the previous slot when the tries run out.
+//!
+//! Where the images and the state actually live is up to the [`SlotStorage`] and [`BootStateStore`] passed in.
+//! The state is kept in a UEFI variable (see [`crate::efi_runtime`]), but the kernel has no storage or FAT driver
+//! yet, so nothing implements [`SlotStorage`] and neither [`install_update`] nor [`mark_pending`] has a caller. The
+//! kernel can't write a slot image until a driver for the ESP provides one.
+
+use crate::println;
+use boot_protocol::{BootSlot, BootState, DEFAULT_BOOT_TRIES, UEFIBootInfo};
+
+static mut BOOT_SLOT: BootSlot = BootSlot::None;
+static mut TRIAL_BOOT: bool = false;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum UpdateError {
+    /// The kernel wasn't booted from a slot, so there's nowhere to install an update
+    NotSlotted,
+    /// The running kernel is an update that hasn't been confirmed yet, installing over the known good slot
+    /// would leave nothing to fall back to
+    TrialInProgress,
+    /// The image couldn't be written to its slot
+    SlotWriteFailed,
+    /// The boot state couldn't be read or written
+    StateUnavailable,
+}
+
+/// Persistent storage for the [`BootState`] shared with the loader
+pub trait BootStateStore {
+    /// `Ok(None)` if no state has been written yet
+    fn load(&mut self) -> Result<Option<BootState>, UpdateError>;
+    fn store(&mut self, state: &BootState) -> Result<(), UpdateError>;
+}
+
+/// Where the kernel images of the slots are stored, usually files on the ESP
+#[allow(dead_code)]
+pub trait SlotStorage {
+    /// Replaces the kernel image in `slot` with `image`
+    fn write_slot(&mut self, slot: BootSlot, image: &[u8]) -> Result<(), UpdateError>;
+}
+
+pub fn init(boot_info: &UEFIBootInfo) {
+    unsafe {
+        BOOT_SLOT = boot_info.boot_slot;
+        TRIAL_BOOT = boot_info.trial_boot;
+    }
+}
+
+/// The slot the running kernel was booted from
+pub fn booted_slot() -> BootSlot {
+    unsafe { BOOT_SLOT }
+}
+
+/// Whether the running kernel is an update that still has to [`confirm_boot`]
+pub fn is_trial_boot() -> bool {
+    unsafe { TRIAL_BOOT }
+}
+
+/// The slot updates get installed into, `None` if the kernel wasn't booted from a slot
+#[allow(dead_code)]
+pub fn inactive_slot() -> Option<BootSlot> {
+    match booted_slot() {
+        BootSlot::None => None,
+        slot => Some(slot.other()),
+    }
+}
+
+/// Writes `image` into the inactive slot and marks it pending, so it's booted from the next reset on
+#[allow(dead_code)]
+pub fn install_update(
+    slots: &mut impl SlotStorage,
+    store: &mut impl BootStateStore,
+    image: &[u8],
+) -> Result<(), UpdateError> {
+    let slot = inactive_slot().ok_or(UpdateError::NotSlotted)?;
+    if is_trial_boot() {
+        return Err(UpdateError::TrialInProgress);
+    }
+
+    // Drop any update that's still pending for this slot first, so the loader never boots a half written image
+    let mut state = current_state(store)?;
+    state.pending = BootSlot::None;
+    state.tries_left = 0;
+    store.store(&state)?;
+
+    slots.write_slot(slot, image)?;
+
+    mark_pending(store, DEFAULT_BOOT_TRIES)
+}
+
+/// Has the loader try the inactive slot for the next `tries` boots
+#[allow(dead_code)]
+pub fn mark_pending(store: &mut impl BootStateStore, tries: u32) -> Result<(), UpdateError> {
+    let slot = inactive_slot().ok_or(UpdateError::NotSlotted)?;
+    if is_trial_boot() {
+        return Err(UpdateError::TrialInProgress);
+    }
+
+    let mut state = current_state(store)?;
+    state.pending = slot;
+    state.tries_left = tries;
+
+    store.store(&state)
+}
+
+/// Makes the running kernel's slot the known good one. Does nothing unless this is a trial boot.
+pub fn confirm_boot(store: &mut impl BootStateStore) -> Result<(), UpdateError> {
+    if !is_trial_boot() {
+        return Ok(());
+    }
+
+    store.store(&BootState {
+        active: booted_slot(),
+        pending: BootSlot::None,
+        tries_left: 0,
+    })?;
+
+    unsafe {
+        TRIAL_BOOT = false;
+    }
+
+    Ok(())
+}
+
+/// The stored state, with the running kernel's slot as the active one. That's what the state says anyway unless
+/// the loader had to fall back to it, in which case it's the slot that's known to work.
+fn current_state(store: &mut impl BootStateStore) -> Result<BootState, UpdateError> {
+    let mut state = store.load()?.unwrap_or(BootState::initial());
+    state.active = booted_slot();
+
+    Ok(state)
+}
+
+pub fn print_boot_slot() {
+    match booted_slot() {
+        BootSlot::None => {}
+        slot if is_trial_boot() => {
+            println!("Booted from slot {:?} (update, not confirmed yet)", slot)
+        }
+        slot => println!("Booted from slot {:?}", slot),
+    }
+}
@@ -0,0 +1,45 @@
+use crate::fs::read_file;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use uefi::boot;
+use uefi::cstr16;
+use uefi::proto::loaded_image::LoadedImage;
+
+/// Gets the kernel command line, either from the loader's LoadOptions (e.g. arguments given in the UEFI shell or
+/// the firmware boot entry), the selected `grove.cfg` entry, or `\cmdline.txt` on the ESP, in that order of priority.
+/// LoadOptions come first so a one-off boot can override the configured command line.
+pub fn read_cmdline(entry_cmdline: Option<&str>) -> String {
+    load_options()
+        .or_else(|| entry_cmdline.map(ToString::to_string))
+        .or_else(|| {
+            let file = read_file(cstr16!("\\cmdline.txt"))?;
+            String::from_utf8(file).ok()
+        })
+        .map(|cmdline| normalize(&cmdline))
+        .unwrap_or_default()
+}
+
+fn load_options() -> Option<String> {
+    let image = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()).ok()?;
+    let options = image.load_options_as_cstr16().ok()?.to_string();
+
+    // The UEFI shell passes the whole command line, which starts with the name of the loader itself
+    let mut args = options.split_whitespace().peekable();
+    if args.peek().is_some_and(|first| first.to_ascii_lowercase().ends_with(".efi")) {
+        args.next();
+    }
+
+    let options = args.collect::<Vec<_>>().join(" ");
+    (!options.is_empty()).then_some(options)
+}
+
+/// Joins all lines into a single space separated command line, skipping comment lines starting with `#`
+fn normalize(cmdline: &str) -> String {
+    cmdline
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
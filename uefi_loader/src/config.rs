@@ -0,0 +1,232 @@
+use crate::fs::read_file;
+use crate::verify::VerifyPolicy;
+use boot_protocol::BootSlot;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use log::warn;
+use uefi::cstr16;
+
+/// Boot configuration, read from `\grove.cfg` on the ESP.
+///
+/// ```text
+/// timeout = 5
+/// default = 0
+/// verify = hash
+///
+/// [GroveOS]
+/// kernel = \kernel.elf
+/// cmdline = loglevel=info
+/// initrd = \initrd.cpio
+/// module = \kernel.sym
+/// module = \drivers\ahci.ko
+/// resolution = 1280x720
+///
+/// [GroveOS (experimental)]
+/// kernel = \kernel-dev.elf
+/// cmdline = loglevel=debug
+///
+/// [GroveOS (self-updating)]
+/// slot_a = \kernel-a.elf
+/// slot_b = \kernel-b.elf
+/// ```
+///
+/// Global options come before the first `[title]` line, everything after it belongs to that entry.
+/// `default` is either the index of an entry or its title. `resolution` is either `WIDTHxHEIGHT`, `best` (the
+/// default, the largest mode the kernel can draw to) or `current` to keep whatever mode the firmware set up. `module` can be given any number of times, each
+/// module is passed to the kernel under its file name.
+///
+/// `verify` decides which kernels are booted: `auto` (the default) checks a kernel's `.sig` or `.sha256` file if it
+/// has one, `hash` also refuses kernels without either and `signature` only boots kernels with a valid signature.
+/// `grove.cfg` itself isn't authenticated, so a loader built with a public key ignores `verify` and always requires a
+/// valid signature. Entries whose kernel fails the check are skipped in favour of the next one.
+///
+/// Kernels can be compressed with `lz4 --content-size`: a `<kernel>.lz4` next to a kernel is booted instead of it,
+/// and `kernel = \kernel.elf.lz4` works too. Signatures and digests are always of the uncompressed kernel.
+///
+/// An entry with both `slot_a` and `slot_b` boots one of those instead of `kernel`, picked from the A/B state the
+/// kernel's updater keeps in a UEFI variable.
+pub struct BootConfig {
+    /// Seconds to wait before booting the default entry, `0` boots it without showing the menu
+    pub timeout: u64,
+    pub default: usize,
+    pub verify: VerifyPolicy,
+    pub entries: Vec<BootEntry>,
+}
+
+pub struct BootEntry {
+    pub title: String,
+    pub kernel: String,
+    pub cmdline: Option<String>,
+    pub initrd: Option<String>,
+    pub modules: Vec<String>,
+    pub resolution: Resolution,
+    pub slot_a: Option<String>,
+    pub slot_b: Option<String>,
+}
+
+/// The graphics mode to switch to before booting an entry
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    Best,
+    Current,
+    Exact(usize, usize),
+}
+
+const CONFIG_PATH: &uefi::CStr16 = cstr16!("\\grove.cfg");
+const DEFAULT_KERNEL_PATH: &str = "\\kernel.elf";
+const DEFAULT_TIMEOUT: u64 = 5;
+
+impl BootEntry {
+    fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            kernel: DEFAULT_KERNEL_PATH.to_string(),
+            cmdline: None,
+            initrd: None,
+            modules: Vec::new(),
+            resolution: Resolution::Best,
+            slot_a: None,
+            slot_b: None,
+        }
+    }
+
+    pub fn has_slots(&self) -> bool {
+        self.slot_a.is_some() && self.slot_b.is_some()
+    }
+
+    /// The kernel to boot for `slot`, which is `kernel` for entries without slots
+    pub fn kernel_path(&self, slot: BootSlot) -> &str {
+        let path = match slot {
+            BootSlot::None => None,
+            BootSlot::A => self.slot_a.as_ref(),
+            BootSlot::B => self.slot_b.as_ref(),
+        };
+
+        path.unwrap_or(&self.kernel)
+    }
+}
+
+impl BootConfig {
+    /// The configuration used when there's no (usable) `grove.cfg`: boot `\kernel.elf` right away
+    fn fallback() -> Self {
+        Self {
+            timeout: 0,
+            default: 0,
+            verify: VerifyPolicy::Auto,
+            entries: vec![BootEntry::new("GroveOS")],
+        }
+    }
+
+    pub fn default_entry(&self) -> &BootEntry {
+        &self.entries[self.default]
+    }
+}
+
+pub fn load_config() -> BootConfig {
+    let Some(file) = read_file(CONFIG_PATH) else {
+        return BootConfig::fallback();
+    };
+
+    let Ok(file) = String::from_utf8(file) else {
+        warn!("grove.cfg is not valid UTF-8, ignoring it");
+        return BootConfig::fallback();
+    };
+
+    let config = parse_config(&file);
+    if config.entries.is_empty() {
+        warn!("grove.cfg has no boot entries, ignoring it");
+        return BootConfig::fallback();
+    }
+
+    config
+}
+
+fn parse_config(file: &str) -> BootConfig {
+    let mut timeout = DEFAULT_TIMEOUT;
+    let mut default = None;
+    let mut verify = VerifyPolicy::Auto;
+    let mut entries: Vec<BootEntry> = Vec::new();
+
+    for (line_number, line) in file.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(title) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            entries.push(BootEntry::new(title.trim()));
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            warn!("grove.cfg:{}: expected `key = value`, ignoring line", line_number);
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        match entries.last_mut() {
+            None => match key {
+                "timeout" => match value.parse() {
+                    Ok(value) => timeout = value,
+                    Err(_) => warn!("grove.cfg:{}: invalid timeout '{}'", line_number, value),
+                },
+                "default" => default = Some(value),
+                "verify" => match parse_verify_policy(value) {
+                    Some(policy) => verify = policy,
+                    None => warn!("grove.cfg:{}: invalid verify policy '{}', expected auto, hash or signature", line_number, value),
+                },
+                _ => warn!("grove.cfg:{}: unknown option '{}'", line_number, key),
+            },
+            Some(entry) => match key {
+                "kernel" => entry.kernel = value.to_string(),
+                "cmdline" => entry.cmdline = Some(value.to_string()),
+                "initrd" => entry.initrd = Some(value.to_string()),
+                "module" => entry.modules.push(value.to_string()),
+                "slot_a" => entry.slot_a = Some(value.to_string()),
+                "slot_b" => entry.slot_b = Some(value.to_string()),
+                "resolution" => match parse_resolution(value) {
+                    Some(resolution) => entry.resolution = resolution,
+                    None => warn!("grove.cfg:{}: invalid resolution '{}', expected WIDTHxHEIGHT, best or current", line_number, value),
+                },
+                _ => warn!("grove.cfg:{}: unknown entry option '{}'", line_number, key),
+            },
+        }
+    }
+
+    for entry in entries.iter().filter(|entry| (entry.slot_a.is_some() || entry.slot_b.is_some()) && !entry.has_slots()) {
+        warn!("grove.cfg: '{}' needs both slot_a and slot_b, booting its kernel option instead", entry.title);
+    }
+
+    let default = match default {
+        None => 0,
+        Some(default) => default.parse::<usize>().ok()
+            .or_else(|| entries.iter().position(|entry| entry.title == default))
+            .filter(|index| *index < entries.len())
+            .unwrap_or_else(|| {
+                warn!("grove.cfg: default entry '{}' doesn't exist, using the first entry", default);
+                0
+            }),
+    };
+
+    BootConfig { timeout, default, verify, entries }
+}
+
+fn parse_resolution(value: &str) -> Option<Resolution> {
+    match value {
+        "best" => Some(Resolution::Best),
+        "current" => Some(Resolution::Current),
+        value => {
+            let (width, height) = value.split_once('x')?;
+            Some(Resolution::Exact(width.trim().parse().ok()?, height.trim().parse().ok()?))
+        }
+    }
+}
+
+fn parse_verify_policy(value: &str) -> Option<VerifyPolicy> {
+    match value {
+        "auto" => Some(VerifyPolicy::Auto),
+        "hash" => Some(VerifyPolicy::Hash),
+        "signature" => Some(VerifyPolicy::Signature),
+        _ => None,
+    }
+}
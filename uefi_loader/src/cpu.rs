@@ -0,0 +1,104 @@
+use core::arch::asm;
+
+const IA32_EFER: u32 = 0xC000_0080;
+const EFER_NXE: u64 = 1 << 11;
+
+const CR0_WP: u64 = 1 << 16;
+
+pub fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+
+    // SAFETY: cpuid is available on every x86_64 cpu. rbx is reserved by LLVM, so it's saved and restored by hand
+    unsafe {
+        asm!(
+            "mov {ebx:r}, rbx",
+            "cpuid",
+            "xchg {ebx:r}, rbx",
+            ebx = out(reg) ebx,
+            inlateout("eax") leaf => eax,
+            inlateout("ecx") subleaf => ecx,
+            lateout("edx") edx,
+            options(nostack, preserves_flags),
+        );
+    }
+
+    (eax, ebx, ecx, edx)
+}
+
+/// # Safety
+/// `msr` has to exist on this cpu
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nostack, preserves_flags));
+
+    (high as u64) << 32 | low as u64
+}
+
+/// # Safety
+/// `msr` has to exist on this cpu, and writing `value` to it must not break the firmware's assumptions
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32, options(nostack, preserves_flags));
+}
+
+/// Whether the cpu supports the execute disable bit in page table entries
+pub fn nx_supported() -> bool {
+    let (max_extended, _, _, _) = cpuid(0x8000_0000, 0);
+    max_extended >= 0x8000_0001 && cpuid(0x8000_0001, 0).3 & (1 << 20) != 0
+}
+
+/// Turns on EFER.NXE, without it the execute disable bit is a reserved bit and using it causes a page fault
+pub fn enable_nx() {
+    // SAFETY: EFER exists on every x86_64 cpu, and NXE only changes how bit 63 of page table entries is treated
+    unsafe { wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NXE) };
+}
+
+/// Turns on CR0.WP so read-only pages are also read-only for the kernel itself
+pub fn enable_write_protect() {
+    // SAFETY: the loader never writes to memory it mapped read-only, so enforcing that is fine
+    unsafe {
+        let cr0: u64;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        asm!("mov cr0, {}", in(reg) cr0 | CR0_WP, options(nostack, preserves_flags));
+    }
+}
+
+/// Whether the cpu can map 1 GiB pages straight from a PDPT entry
+pub fn huge_pages_1g_supported() -> bool {
+    let (max_extended, _, _, _) = cpuid(0x8000_0000, 0);
+    max_extended >= 0x8000_0001 && cpuid(0x8000_0001, 0).3 & (1 << 26) != 0
+}
+
+/// A random number from RDRAND, `None` if the cpu doesn't have it or it keeps failing
+pub fn rdrand() -> Option<u64> {
+    if cpuid(1, 0).2 & (1 << 30) == 0 {
+        return None;
+    }
+
+    // RDRAND can fail when the entropy source is drained, Intel recommends retrying 10 times
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+
+        // SAFETY: cpuid says RDRAND is supported
+        unsafe {
+            asm!("rdrand {value}", "setc {ok}", value = out(reg) value, ok = out(reg_byte) ok, options(nomem, nostack));
+        }
+
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+
+    // SAFETY: the TSC is available on every x86_64 cpu
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+
+    (high as u64) << 32 | low as u64
+}
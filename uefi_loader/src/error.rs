@@ -0,0 +1,41 @@
+use core::fmt::{Display, Formatter};
+
+/// Everything that can stop the loader from booting a kernel
+#[derive(Debug)]
+pub enum LoaderError {
+    /// None of the boot entries has a kernel that could be read and verified
+    NoBootableKernel,
+    BadElf(goblin::error::Error),
+    NoLoadableSegments,
+    SegmentOutsideFile { vaddr: u64 },
+    WritableExecutableSegment { vaddr: u64 },
+    UnsupportedRelocation { kind: u32, offset: u64 },
+    /// A relocation points outside of the kernel's segments
+    RelocationOutsideImage { offset: u64 },
+    NoGraphics,
+    NoUsableGraphicsMode,
+    /// The firmware ran out of memory for the named allocation
+    OutOfMemory(&'static str),
+    MemoryMapUnavailable,
+    /// The final memory map has more entries than the room reserved for it before exiting boot services
+    MemoryMapTooLarge { entries: usize, capacity: usize },
+}
+
+impl Display for LoaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LoaderError::NoBootableKernel => write!(f, "No boot entry has a kernel that can be read and verified"),
+            LoaderError::BadElf(err) => write!(f, "The kernel isn't a valid ELF file: {}", err),
+            LoaderError::NoLoadableSegments => write!(f, "The kernel has no loadable segments"),
+            LoaderError::SegmentOutsideFile { vaddr } => write!(f, "Kernel segment at {:#x} lies outside of the file", vaddr),
+            LoaderError::WritableExecutableSegment { vaddr } => write!(f, "Kernel segment at {:#x} is both writable and executable", vaddr),
+            LoaderError::UnsupportedRelocation { kind, offset } => write!(f, "Unsupported kernel relocation type {} at {:#x}", kind, offset),
+            LoaderError::RelocationOutsideImage { offset } => write!(f, "Kernel relocation at {:#x} lies outside of the kernel", offset),
+            LoaderError::NoGraphics => write!(f, "No Graphics Output Protocol"),
+            LoaderError::NoUsableGraphicsMode => write!(f, "No graphics mode with a framebuffer the kernel can draw to"),
+            LoaderError::OutOfMemory(what) => write!(f, "Out of memory allocating {}", what),
+            LoaderError::MemoryMapUnavailable => write!(f, "Failed to read the memory map"),
+            LoaderError::MemoryMapTooLarge { entries, capacity } => write!(f, "The memory map has {} entries, but there's only room for {}", entries, capacity),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::error::LoaderError;
+use core::fmt::Write;
+use core::time::Duration;
+use uefi::boot;
+use uefi::proto::console::text::Color;
+use uefi::system;
+
+/// Shows why the loader couldn't boot and waits for a key press, after which the loader returns to the firmware
+pub fn show(err: &LoaderError) {
+    system::with_stdout(|stdout| {
+        let _ = stdout.set_color(Color::LightGray, Color::Black);
+        let _ = stdout.clear();
+
+        let _ = stdout.set_color(Color::White, Color::Red);
+        let _ = writeln!(stdout, " GroveOS can't be booted ");
+        let _ = stdout.set_color(Color::LightGray, Color::Black);
+
+        let _ = writeln!(stdout, "\n{}\n", err);
+        let _ = writeln!(stdout, "The full log has been written to \\grove\\boot.log on the boot volume.");
+        let _ = writeln!(stdout, "Press any key to return to the firmware.");
+    });
+
+    let Some(key_event) = system::with_stdin(|stdin| stdin.wait_for_key_event()) else {
+        // Without a keyboard, give whoever is watching some time to read the error
+        boot::stall(Duration::from_secs(30));
+        return;
+    };
+
+    let _ = boot::wait_for_event(&mut [key_event]);
+    let _ = system::with_stdin(|stdin| stdin.read_key());
+}
@@ -0,0 +1,70 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+use uefi::boot::{self, AllocateType, MemoryType, PAGE_SIZE};
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode};
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::CStr16;
+
+/// Opens the root directory of the volume the loader was started from (the ESP)
+pub fn open_boot_volume() -> Option<Directory> {
+    let image = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()).ok()?;
+    let mut fs = boot::open_protocol_exclusive::<SimpleFileSystem>(image.device()?).ok()?;
+
+    fs.open_volume().ok()
+}
+
+/// Reads a whole file from the boot volume, returning `None` if it doesn't exist or can't be read
+pub fn read_file(path: &CStr16) -> Option<Vec<u8>> {
+    let mut volume = open_boot_volume()?;
+    let mut file = volume.open(path, FileMode::Read, FileAttribute::empty()).ok()?.into_regular_file()?;
+
+    let info = file.get_boxed_info::<FileInfo>().ok()?;
+    let mut buffer = vec![0u8; info.file_size() as usize];
+
+    let read = file.read(&mut buffer).ok()?;
+    buffer.truncate(read);
+
+    Some(buffer)
+}
+
+/// Reads a whole file from the boot volume straight into freshly allocated pages of type `memory_type`.
+/// Returns the start of the pages and the size of the file, the rest of the last page is zeroed.
+pub fn read_file_to_pages(path: &CStr16, memory_type: MemoryType) -> Option<(NonNull<u8>, usize)> {
+    let mut volume = open_boot_volume()?;
+    let mut file = volume.open(path, FileMode::Read, FileAttribute::empty()).ok()?.into_regular_file()?;
+
+    let size = file.get_boxed_info::<FileInfo>().ok()?.file_size() as usize;
+    let pages = size.div_ceil(PAGE_SIZE).max(1);
+    let memory = boot::allocate_pages(AllocateType::AnyPages, memory_type, pages).ok()?;
+
+    // SAFETY: allocate_pages returned `pages` pages, which are identity mapped while boot services are active
+    let buffer = unsafe { core::slice::from_raw_parts_mut(memory.as_ptr(), pages * PAGE_SIZE) };
+    buffer.fill(0);
+
+    match file.read(&mut buffer[..size]) {
+        Ok(read) if read == size => Some((memory, size)),
+        _ => {
+            // SAFETY: the pages were allocated above and nothing else has a reference to them
+            let _ = unsafe { boot::free_pages(memory, pages) };
+            None
+        }
+    }
+}
+
+/// Replaces the file `name` in the directory `directory` (which is created if needed) on the boot volume with `data`
+pub fn write_file(directory: &CStr16, name: &CStr16, data: &[u8]) -> Option<()> {
+    let mut volume = open_boot_volume()?;
+    let mut directory = volume.open(directory, FileMode::CreateReadWrite, FileAttribute::DIRECTORY).ok()?.into_directory()?;
+
+    // Opening an existing file doesn't truncate it, so the old one is deleted first
+    if let Ok(old) = directory.open(name, FileMode::ReadWrite, FileAttribute::empty()) {
+        old.delete().ok()?;
+    }
+
+    let mut file = directory.open(name, FileMode::CreateReadWrite, FileAttribute::empty()).ok()?.into_regular_file()?;
+    file.write(data).ok()?;
+
+    file.flush().ok()
+}
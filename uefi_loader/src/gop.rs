@@ -0,0 +1,95 @@
+use crate::config::Resolution;
+use crate::error::LoaderError;
+use boot_protocol::{Framebuffer, PixelBitmask, PixelFormat};
+use log::{info, warn};
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol};
+use uefi::proto::console::gop::{self, GraphicsOutput, Mode, ModeInfo};
+
+/// Switches to the graphics mode asked for by `resolution` and describes its framebuffer for the kernel
+pub fn init_framebuffer(resolution: Resolution) -> Result<Framebuffer, LoaderError> {
+    let mut gop = open_gop().ok_or(LoaderError::NoGraphics)?;
+
+    info!("Opened Graphics Output");
+
+    let current_usable = pixel_format(&gop.current_mode_info()).is_some();
+    let mode = match resolution {
+        Resolution::Current if current_usable => None,
+        Resolution::Exact(width, height) => {
+            let mode = gop.modes().find(|mode| mode.info().resolution() == (width, height) && is_usable(mode));
+            if mode.is_none() {
+                warn!("No usable {}x{} graphics mode, using the best one instead", width, height);
+            }
+
+            mode.or_else(|| best_mode(&gop))
+        }
+        _ => best_mode(&gop),
+    };
+
+    if let Some(mode) = mode {
+        let (width, height) = mode.info().resolution();
+        if gop.set_mode(&mode).is_err() {
+            warn!("Failed to switch to {}x{}, keeping the current mode", width, height);
+        }
+    }
+
+    let info = gop.current_mode_info();
+    let (format, bitmask) = pixel_format(&info).ok_or(LoaderError::NoUsableGraphicsMode)?;
+
+    let (width, height) = info.resolution();
+    info!("Using {}x{} graphics mode ({:?}, stride {})", width, height, format, info.stride());
+
+    Ok(Framebuffer {
+        base: gop.frame_buffer().as_mut_ptr() as *mut u32,
+        size: info.stride() * height,
+        width,
+        height,
+        stride: info.stride(),
+        format,
+        bitmask,
+    })
+}
+
+fn open_gop() -> Option<ScopedProtocol<GraphicsOutput>> {
+    let handle = boot::get_handle_for_protocol::<GraphicsOutput>().ok()?;
+
+    // SAFETY: the console still uses the GOP, so it's opened non-exclusively. The mode switch in init_framebuffer
+    // happens before anything else reads the mode.
+    unsafe {
+        boot::open_protocol::<GraphicsOutput>(OpenProtocolParams { handle, agent: boot::image_handle(), controller: None }, OpenProtocolAttributes::GetProtocol)
+    }.ok()
+}
+
+/// The largest mode the kernel can draw to
+fn best_mode(gop: &GraphicsOutput) -> Option<Mode> {
+    gop.modes()
+        .filter(is_usable)
+        .max_by_key(|mode| {
+            let (width, height) = mode.info().resolution();
+            width * height
+        })
+}
+
+fn is_usable(mode: &Mode) -> bool {
+    pixel_format(mode.info()).is_some()
+}
+
+/// The kernel only draws to linear framebuffers with 32-bit pixels
+fn pixel_format(info: &ModeInfo) -> Option<(PixelFormat, PixelBitmask)> {
+    match info.pixel_format() {
+        gop::PixelFormat::Rgb => Some((PixelFormat::Rgb, PixelBitmask::default())),
+        gop::PixelFormat::Bgr => Some((PixelFormat::Bgr, PixelBitmask::default())),
+        gop::PixelFormat::Bitmask => {
+            let mask = info.pixel_bitmask()?;
+            let all = mask.red | mask.green | mask.blue | mask.reserved;
+
+            // Anything whose highest bit is below bit 24 uses smaller pixels
+            (all.leading_zeros() < 8).then_some((PixelFormat::Bitmask, PixelBitmask {
+                red: mask.red,
+                green: mask.green,
+                blue: mask.blue,
+                reserved: mask.reserved,
+            }))
+        }
+        gop::PixelFormat::BltOnly => None,
+    }
+}
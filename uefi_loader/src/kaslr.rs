@@ -0,0 +1,48 @@
+use crate::cpu;
+use log::{info, warn};
+use uefi::boot;
+use uefi::proto::rng::Rng;
+
+/// Slides are multiples of 2 MiB, so the kernel keeps the alignment it was linked with
+const SLIDE_ALIGN: u64 = 0x20_0000;
+
+/// Picks a random offset to load the kernel at. The kernel is built with the kernel code model, so it has to stay
+/// within the top 2 GiB of the address space: it's linked at the bottom of it and only ever moved up.
+///
+/// `image_end` is the end of the last segment at its link address.
+pub fn choose_slide(image_end: u64) -> u64 {
+    // Number of SLIDE_ALIGN steps between the end of the image and the end of the address space
+    let slots = (u64::MAX - image_end.next_multiple_of(SLIDE_ALIGN) + 1) / SLIDE_ALIGN;
+    if slots == 0 {
+        warn!("Kernel image doesn't leave room for KASLR");
+        return 0;
+    }
+
+    // The last slot would put the end of the image at 2^64
+    (random_u64() % slots) * SLIDE_ALIGN
+}
+
+/// Gets randomness from the UEFI RNG protocol, falling back to RDRAND and then to the TSC
+fn random_u64() -> u64 {
+    if let Some(value) = uefi_rng() {
+        return value;
+    }
+
+    if let Some(value) = cpu::rdrand() {
+        info!("No UEFI RNG, using RDRAND for KASLR");
+        return value;
+    }
+
+    warn!("No UEFI RNG or RDRAND, using the TSC for KASLR, the kernel base will be predictable");
+    cpu::rdtsc()
+}
+
+fn uefi_rng() -> Option<u64> {
+    let handle = boot::get_handle_for_protocol::<Rng>().ok()?;
+    let mut rng = boot::open_protocol_exclusive::<Rng>(handle).ok()?;
+
+    let mut value = [0u8; 8];
+    rng.get_rng(None, &mut value).ok()?;
+
+    Some(u64::from_le_bytes(value))
+}
@@ -0,0 +1,73 @@
+use crate::fs;
+use alloc::string::String;
+use core::fmt::Write;
+use log::{LevelFilter, Log, Metadata, Record};
+use uefi::{cstr16, system, CStr16};
+
+const LOG_DIRECTORY: &CStr16 = cstr16!("\\grove");
+const LOG_FILE: &CStr16 = cstr16!("boot.log");
+
+/// Everything logged so far, written to `\grove\boot.log` by [`save`]
+static mut LOG: String = String::new();
+/// Cleared when boot services are about to go away, since both the console and the allocator go with them
+static mut ENABLED: bool = false;
+
+static LOGGER: Logger = Logger;
+
+/// Logs to the console and keeps a copy of everything for the log file
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        // SAFETY: the loader is single threaded
+        unsafe { ENABLED }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // SAFETY: the loader is single threaded, so nothing else can be using LOG
+        #[allow(static_mut_refs)]
+        unsafe {
+            let _ = writeln!(LOG, "[{:>5}] {}", record.level(), record.args());
+        }
+
+        system::with_stdout(|stdout| {
+            let _ = writeln!(stdout, "[{:>5}] {}", record.level(), record.args());
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    // SAFETY: the loader is single threaded
+    unsafe { ENABLED = true };
+
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
+
+/// Writes everything logged so far to `\grove\boot.log` on the ESP, replacing the log of the previous boot
+pub fn save() {
+    // SAFETY: the loader is single threaded, so nothing is logging while the file is written
+    #[allow(static_mut_refs)]
+    let log = unsafe { LOG.as_bytes() };
+
+    if fs::write_file(LOG_DIRECTORY, LOG_FILE, log).is_none() {
+        system::with_stdout(|stdout| {
+            let _ = writeln!(stdout, "Failed to write \\grove\\boot.log");
+        });
+    }
+}
+
+/// Saves the log and stops logging, has to be called before exiting boot services
+pub fn finish() {
+    save();
+
+    // SAFETY: the loader is single threaded
+    unsafe { ENABLED = false };
+}
@@ -0,0 +1,201 @@
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+const FRAME_MAGIC: u32 = 0x184D_2204;
+
+const FLG_VERSION_MASK: u8 = 0b1100_0000;
+const FLG_VERSION: u8 = 0b0100_0000;
+const FLG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLG_CONTENT_SIZE: u8 = 1 << 3;
+const FLG_CONTENT_CHECKSUM: u8 = 1 << 2;
+const FLG_DICT_ID: u8 = 1 << 0;
+
+/// Set in a block's size when the block is stored uncompressed
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+
+/// Every match is at least this long, the token only stores the length on top of it
+const MIN_MATCH: usize = 4;
+
+#[derive(Debug)]
+pub enum Lz4Error {
+    BadMagic,
+    UnsupportedVersion,
+    /// The frame doesn't say how big the decompressed data is, compress with `lz4 --content-size`
+    NoContentSize,
+    /// The data ends in the middle of the frame
+    Truncated,
+    /// A block refers to data outside of what has been decompressed so far
+    Corrupt,
+    /// The decompressed data isn't the size the frame header says it is
+    SizeMismatch { expected: usize, found: usize },
+    /// There's not enough memory for the content size in the frame header
+    OutOfMemory { size: usize },
+}
+
+impl Display for Lz4Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Lz4Error::BadMagic => write!(f, "not an LZ4 frame"),
+            Lz4Error::UnsupportedVersion => write!(f, "unsupported LZ4 frame version"),
+            Lz4Error::NoContentSize => write!(f, "LZ4 frame has no content size, compress with --content-size"),
+            Lz4Error::Truncated => write!(f, "LZ4 frame is truncated"),
+            Lz4Error::Corrupt => write!(f, "LZ4 data is corrupt"),
+            Lz4Error::SizeMismatch { expected, found } => write!(f, "LZ4 frame decompressed to {} bytes instead of {}", found, expected),
+            Lz4Error::OutOfMemory { size } => write!(f, "not enough memory to decompress {} bytes", size),
+        }
+    }
+}
+
+/// Decompresses a single LZ4 frame. The frame has to carry the content size, so the output can be allocated in one go.
+///
+/// Checksums in the frame are skipped rather than checked, the kernel's digest or signature covers the decompressed data.
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, Lz4Error> {
+    let mut reader = Reader { data: input, pos: 0 };
+
+    if reader.u32()? != FRAME_MAGIC {
+        return Err(Lz4Error::BadMagic);
+    }
+
+    let flags = reader.u8()?;
+    let _block_descriptor = reader.u8()?;
+    if flags & FLG_VERSION_MASK != FLG_VERSION {
+        return Err(Lz4Error::UnsupportedVersion);
+    }
+    if flags & FLG_CONTENT_SIZE == 0 {
+        return Err(Lz4Error::NoContentSize);
+    }
+
+    let content_size = usize::try_from(reader.u64()?).map_err(|_| Lz4Error::Corrupt)?;
+    if flags & FLG_DICT_ID != 0 {
+        reader.skip(4)?;
+    }
+    let _header_checksum = reader.u8()?;
+
+    // The content size comes straight from the file, so a bogus one shouldn't take the loader down
+    let mut output = Vec::new();
+    output.try_reserve_exact(content_size).map_err(|_| Lz4Error::OutOfMemory { size: content_size })?;
+    output.resize(content_size, 0);
+    let mut written = 0;
+
+    loop {
+        let block_size = reader.u32()?;
+        if block_size == 0 {
+            break;
+        }
+
+        let block = reader.take((block_size & !BLOCK_UNCOMPRESSED) as usize)?;
+        if block_size & BLOCK_UNCOMPRESSED != 0 {
+            output.get_mut(written..written + block.len())
+                .ok_or(Lz4Error::SizeMismatch { expected: content_size, found: written + block.len() })?
+                .copy_from_slice(block);
+            written += block.len();
+        } else {
+            // Blocks are decompressed straight into the output, so matches reaching back into earlier blocks
+            // (linked blocks) just work
+            written = decompress_block(block, &mut output, written)?;
+        }
+
+        if flags & FLG_BLOCK_CHECKSUM != 0 {
+            reader.skip(4)?;
+        }
+    }
+
+    if flags & FLG_CONTENT_CHECKSUM != 0 {
+        reader.skip(4)?;
+    }
+
+    if written != content_size {
+        return Err(Lz4Error::SizeMismatch { expected: content_size, found: written });
+    }
+
+    Ok(output)
+}
+
+/// Decompresses one block into `output` at `pos`, returning the position after the decompressed data
+fn decompress_block(block: &[u8], output: &mut [u8], mut pos: usize) -> Result<usize, Lz4Error> {
+    let mut reader = Reader { data: block, pos: 0 };
+    let size = output.len();
+
+    loop {
+        let token = reader.u8()?;
+
+        let literal_len = reader.length(token >> 4)?;
+        let literals = reader.take(literal_len)?;
+        output.get_mut(pos..pos + literal_len)
+            .ok_or(Lz4Error::SizeMismatch { expected: size, found: pos + literal_len })?
+            .copy_from_slice(literals);
+        pos += literal_len;
+
+        // The last sequence of a block only has literals
+        if reader.pos == block.len() {
+            return Ok(pos);
+        }
+
+        let offset = reader.u16()? as usize;
+        if offset == 0 || offset > pos {
+            return Err(Lz4Error::Corrupt);
+        }
+
+        let match_len = reader.length(token & 0xF)? + MIN_MATCH;
+        if pos + match_len > size {
+            return Err(Lz4Error::SizeMismatch { expected: size, found: pos + match_len });
+        }
+
+        // Matches can overlap the bytes they produce (e.g. runs of the same byte), so this has to go byte by byte
+        for i in pos..pos + match_len {
+            output[i] = output[i - offset];
+        }
+        pos += match_len;
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Lz4Error> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len).ok_or(Lz4Error::Truncated)?).ok_or(Lz4Error::Truncated)?;
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), Lz4Error> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, Lz4Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Lz4Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Lz4Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Lz4Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a literal or match length: `nibble` from the token, followed by extra bytes if it's 15. Each extra byte is
+    /// added to the length, and another one follows as long as they're 255.
+    fn length(&mut self, nibble: u8) -> Result<usize, Lz4Error> {
+        let mut len = nibble as usize;
+        if nibble == 15 {
+            loop {
+                let byte = self.u8()?;
+                len += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+
+        Ok(len)
+    }
+}
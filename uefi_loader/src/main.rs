@@ -1,19 +1,45 @@
 #![no_main]
 #![no_std]
 
+extern crate alloc;
+
+mod cmdline;
+mod config;
+mod cpu;
+mod error;
+mod error_screen;
+mod fs;
+mod gop;
+mod kaslr;
+mod logger;
+mod lz4;
+mod menu;
+mod modules;
+mod mp;
+mod runtime;
+mod slots;
+mod verify;
+
+use alloc::format;
+use alloc::vec::Vec;
 use core::alloc::{GlobalAlloc, Layout};
 use core::arch::asm;
 use core::ptr::NonNull;
+use crate::config::BootEntry;
+use crate::error::LoaderError;
+use crate::slots::SlotChoice;
+use crate::verify::{Verification, VerifyPolicy};
+use boot_protocol::{Framebuffer, LoaderTimestamps, MemoryRegion, MemoryRegionKind, UEFIBootInfo, KERNEL_MEMORY_TYPE, PAGE_TABLE_MEMORY_TYPE};
+use goblin::elf::header::ET_DYN;
+use goblin::elf::reloc::{R_X86_64_NONE, R_X86_64_RELATIVE};
 use goblin::elf::Elf;
-use goblin::elf::program_header::PT_LOAD;
-use log::info;
-use uefi::boot::{AllocateType, MemoryType, OpenProtocolAttributes, OpenProtocolParams, PAGE_SIZE};
-use uefi::mem::memory_map::MemoryMap;
+use goblin::elf::program_header::{ProgramHeader, PF_W, PF_X, PT_LOAD};
+use log::{error, info, warn};
+use uefi::boot::{AllocateType, MemoryType, PAGE_SIZE};
+use uefi::mem::memory_map::{MemoryMap, MemoryMapOwned};
 use uefi::prelude::*;
-use uefi::proto::console::gop::GraphicsOutput;
-use uefi::proto::loaded_image::LoadedImage;
-use uefi::proto::media::file::{File, FileAttribute, FileHandle, FileInfo, FileMode};
-use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID};
+use uefi::CString16;
 
 #[global_allocator]
 static ALLOCATOR: Allocator = Allocator;
@@ -22,7 +48,7 @@ pub struct Allocator;
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        boot::allocate_pool(MemoryType::LOADER_DATA, layout.size()).unwrap().as_ptr()
+        boot::allocate_pool(MemoryType::LOADER_DATA, layout.size()).map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
@@ -32,94 +58,146 @@ unsafe impl GlobalAlloc for Allocator {
 
 #[entry]
 fn main() -> Status {
-    uefi::helpers::init().unwrap();
+    let loader_entry = cpu::rdtsc();
 
-    let mut kernel = load_kernel().expect("Failed to load the kernel");
-
-    let mut file_info = [0u8;0];
-    let err = kernel.get_info::<FileInfo>(&mut file_info).err().unwrap();
-    let file_info = boot::allocate_pool(MemoryType::LOADER_DATA, err.data().unwrap()).unwrap();
-
-    // SAFETY: the pointer that UEFI returns has the length passed in, so creating a fat pointer is ok
-    let file_info = unsafe { core::slice::from_raw_parts_mut(file_info.as_ptr(), err.data().unwrap()) };
-    let info = kernel.get_info::<FileInfo>(file_info).unwrap();
-
-    let mut kernel = kernel.into_regular_file().unwrap();
+    if uefi::helpers::init().is_err() {
+        return Status::ABORTED;
+    }
+    logger::init();
 
-    let (elf, kernel_file) = {
-        let header = boot::allocate_pool(MemoryType::LOADER_DATA, info.file_size() as _).unwrap();
+    match prepare(loader_entry) {
+        Ok(handoff) => boot(handoff),
+        Err(err) => {
+            error!("{}", err);
+            logger::save();
+            error_screen::show(&err);
 
-        // SAFETY: the pointer that UEFI returns has the length passed in, so creating a fat pointer is ok
-        let kernel_file = unsafe { core::slice::from_raw_parts_mut(header.as_ptr(), info.file_size() as _) };
-        let header = unsafe { core::slice::from_raw_parts_mut(header.as_ptr(), info.file_size() as _) };
+            Status::LOAD_ERROR
+        }
+    }
+}
 
-        // Trick here, since header and kernel_file point to the same spot, we only need to write to one
-        // Having both just gets around the compiler complaining about header being borrowed by Elf::parse()
-        let _ = kernel.read(header);
+/// Everything [`boot`] needs once the kernel is loaded and the boot info is filled in
+struct Handoff {
+    pml4: &'static mut PageTable,
+    boot_info: *mut UEFIBootInfo,
+    regions: &'static mut [MemoryRegion],
+    runtime_map: runtime::RuntimeMap,
+    kernel_entry: u64,
+    stack_top: u64,
+    nx: bool,
+}
 
-        (Elf::parse(header).expect("Kernel corrupt"), kernel_file)
-    };
+/// Loads the kernel and sets up everything it's handed, while boot services are still around to report errors
+fn prepare(loader_entry: u64) -> Result<Handoff, LoaderError> {
+    let mut timestamps = LoaderTimestamps { loader_entry, ..LoaderTimestamps::empty() };
+
+    let config = config::load_config();
+    let selected = menu::select_entry(&config);
+    timestamps.entry_selected = cpu::rdtsc();
+
+    // If the chosen entry's kernel can't be read or fails verification, the other entries are tried in order
+    let (entry, kernel_file, slot) = core::iter::once(selected)
+        .chain((0..config.entries.len()).filter(|index| *index != selected))
+        .find_map(|index| {
+            let entry = &config.entries[index];
+            read_entry_kernel(entry, config.verify).map(|(kernel, slot)| (entry, kernel, slot))
+        })
+        .ok_or(LoaderError::NoBootableKernel)?;
+    timestamps.kernel_read = cpu::rdtsc();
+
+    let elf = Elf::parse(kernel_file).map_err(LoaderError::BadElf)?;
     
     info!("Getting Graphics info...");
 
-    let gop_handle = boot::get_handle_for_protocol::<GraphicsOutput>().unwrap();
-    
-    // SAFETY: we're just getting information, not changing anything (like the mode) so shared access is fine
-    let mut gop = unsafe {
-        boot::open_protocol::<GraphicsOutput>(OpenProtocolParams { handle: gop_handle, agent: boot::image_handle(), controller: None }, OpenProtocolAttributes::GetProtocol)
-    }.expect("Failed to get GraphicsOutput");
-    
-    info!("Opened Graphics Output");
-    
-    // SAFETY: if the GraphicsOutput protocol opened successfully, then the framebuffer should contain a valid address
-    let (framebuffer, width, height) = unsafe {
-        let (width, height) = gop.current_mode_info().resolution();
-        (core::slice::from_raw_parts_mut(gop.frame_buffer().as_mut_ptr() as *mut u32, height * gop.current_mode_info().stride()), width, height)
-    };
+    let framebuffer = gop::init_framebuffer(entry.resolution).unwrap_or_else(|err| {
+        warn!("{}, booting without a framebuffer", err);
+        Framebuffer::empty()
+    });
 
-    let pml4 = allocate_table();
-
-    for phdr in &elf.program_headers {
-        if phdr.p_type == PT_LOAD {
-            // We need to copy the parts of the header to page-aligned spaces.
-            // The kernel code doesn't really care where it's placed, since it'll just use the contiguous virtual space
-            let pages = (phdr.p_memsz + 0x1000 - 1) / 0x1000;
-            let allocated_space = boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages as _).expect("Failed to allocate correct pages for kernel elf");
-            
-            for i in 0..((phdr.p_memsz + 0x1000 - 1) / 0x1000) {
-                // SAFETY: both kernel_file and allocated_space are valid pointers, and the length is within bounds, so .offset and .copy_to_nonoverlapping are safe to use
-                unsafe {
-                    kernel_file.as_ptr().offset(i as isize * 0x1000 + phdr.p_offset as isize)
-                        .copy_to_nonoverlapping(allocated_space.as_ptr().offset(i as isize * 0x1000), PAGE_SIZE);
-                }
+    let pml4 = allocate_table()?;
 
-                map_page(pml4, phdr.p_vaddr + i * 0x1000, allocated_space.as_ptr() as u64 + i * 0x1000, PAGE_WRITE);
-            }
-        }
+    // Without NX support the execute disable bit would be a reserved bit, so the kernel's data can't be made non-executable
+    let nx = cpu::nx_supported();
+    if !nx {
+        warn!("CPU doesn't support NX, kernel data will be executable");
     }
 
-    info!("Finished mapping kernel! Entry @ {:x}", elf.entry);
-    
-    map_static(pml4);
-    
-    info!("PML4 formatted for kernel.");
-    
-    for i in 0..(((framebuffer.len() * size_of::<u32>()) + 0x1000 - 1) / 0x1000) as u64 {
-        map_page(pml4, framebuffer.as_ptr() as u64 + i * 0x1000, framebuffer.as_ptr() as u64 + i * 0x1000, PAGE_WRITE);
+    // The command line lives in pool memory, which the kernel can still read after boot services are gone
+    let cmdline = cmdline::read_cmdline(entry.cmdline.as_deref()).leak();
+    info!("Kernel command line: {}", cmdline);
+
+    let slide = if cmdline.split_whitespace().any(|param| param == "nokaslr") {
+        info!("KASLR disabled on the command line");
+        0
+    } else if elf.header.e_type != ET_DYN {
+        warn!("Kernel isn't relocatable, loading it at its link address");
+        0
+    } else {
+        let image_end = elf.program_headers.iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .map(|phdr| phdr.p_vaddr + phdr.p_memsz)
+            .max()
+            .ok_or(LoaderError::NoLoadableSegments)?;
+
+        kaslr::choose_slide(image_end)
+    };
+
+    for phdr in elf.program_headers.iter().filter(|phdr| phdr.p_type == PT_LOAD) {
+        load_segment(pml4, kernel_file, phdr, slide, nx)?;
     }
+
+    apply_relocations(pml4, &elf, slide)?;
+
+    let kernel_entry = elf.entry + slide;
+    info!("Finished mapping kernel! Entry @ {:x} (slide {:x})", kernel_entry, slide);
+    timestamps.kernel_loaded = cpu::rdtsc();
+
+    let (stack_bottom, stack_top) = allocate_stack(pml4, nx)?;
+    info!("Kernel stack @ {:x}..{:x}", stack_bottom, stack_top);
     
-    let boot_info = boot::allocate_pool(MemoryType::LOADER_DATA, size_of::<UEFIBootInfo>()).unwrap();
+    let rsdp = find_rsdp();
+    info!("ACPI RSDP @ {:x}", rsdp);
+
+    // Like the command line, the module list stays in pool memory for the kernel to copy
+    let modules = modules::load_modules(entry).leak();
+
+    // The processor list is read now, since MP services go away with the rest of boot services
+    let processors = mp::read_processors().leak();
+
+    let boot_info = boot::allocate_pool(MemoryType::LOADER_DATA, size_of::<UEFIBootInfo>())
+        .map_err(|_| LoaderError::OutOfMemory("the boot info"))?;
     let boot_info = boot_info.as_ptr() as *mut UEFIBootInfo;
     
-    // SAFETY: allocate_pool returns a valid pointer, so dereferencing it is safe
+    // SAFETY: allocate_pool returns a valid pointer, so writing to and dereferencing it is safe
     unsafe {
-        (*boot_info).framebuffer = framebuffer.as_mut_ptr();
-        (*boot_info).framebuffer_size = framebuffer.len();
-        (*boot_info).framebuffer_width = width;
-        (*boot_info).framebuffer_height = height;
+        boot_info.write(UEFIBootInfo::new());
+
+        (*boot_info).hhdm_offset = HHDM_OFFSET;
+        (*boot_info).kernel_slide = slide;
+
+        (*boot_info).stack_bottom = stack_bottom;
+        (*boot_info).stack_top = stack_top;
+
+        (*boot_info).boot_slot = slot.slot;
+        (*boot_info).trial_boot = slot.trial;
+
+        (*boot_info).framebuffer = framebuffer;
+
+        (*boot_info).acpi_rsdp = rsdp;
+
+        (*boot_info).cmdline = cmdline.as_ptr();
+        (*boot_info).cmdline_len = cmdline.len();
+
+        (*boot_info).modules = modules.as_ptr();
+        (*boot_info).modules_len = modules.len();
+
+        (*boot_info).processors = processors.as_ptr();
+        (*boot_info).processors_len = processors.len();
     }
+    timestamps.boot_info_ready = cpu::rdtsc();
 
-    let prev_map = boot::memory_map(MemoryType::LOADER_DATA).unwrap();
+    let prev_map = boot::memory_map(MemoryType::LOADER_DATA).map_err(|_| LoaderError::MemoryMapUnavailable)?;
 
     let mut memsz = 0usize;
 
@@ -134,56 +212,252 @@ fn main() -> Status {
         if excluded_types.contains(&entry.ty) { continue; } // Skip reserved memory
         memsz += entry.page_count as usize;
     }
-    
-    let memory_bitmap_size = memsz / 8;
-    let memory_bitmap = boot::allocate_pool(MemoryType::LOADER_DATA, memory_bitmap_size).unwrap();
-    let memory_bitmap = memory_bitmap.as_ptr();
 
-    for entry in prev_map.entries() {
-        if excluded_types.contains(&entry.ty) { continue; } // Skip reserved memory
-        for i in 0..entry.page_count {
-            map_page(pml4, entry.phys_start + i * PAGE_SIZE as u64, (if entry.virt_start == 0 { entry.phys_start } else { entry.virt_start }) + i * PAGE_SIZE as u64, PAGE_WRITE);
-        }
-    }
-    
-    unsafe {
-        (*boot_info).memory_bitmap_size = memory_bitmap_size;
-        (*boot_info).memory_bitmap = memory_bitmap;
-    }
+    // Map at least the first 4 GiB, which is where the firmware usually puts MMIO like the APICs,
+    // and make sure the framebuffer is covered even if it isn't part of the memory map
+    let phys_end = prev_map.entries()
+        .filter(|entry| !excluded_types.contains(&entry.ty))
+        .map(|entry| entry.phys_start + entry.page_count * PAGE_SIZE as u64)
+        .chain([MIN_MAPPED_MEMORY, framebuffer.base as u64 + framebuffer.byte_len() as u64])
+        .max()
+        .unwrap_or(MIN_MAPPED_MEMORY);
+
+    // The identity mapping keeps the loader running after switching page tables, the direct map is what the kernel uses
+    let huge_1g = cpu::huge_pages_1g_supported();
+    map_physical_memory(pml4, 0, phys_end, huge_1g)?;
+    map_physical_memory(pml4, HHDM_OFFSET, phys_end, huge_1g)?;
+
+    info!("PML4 formatted for kernel. Physical memory up to {:x} mapped at {:x}", phys_end, HHDM_OFFSET);
 
-    info!("UEFI memory map copied!");
     info!("MemorySize found to be {}mb ({} bytes)", memsz * PAGE_SIZE / (1e+6 as usize), memsz * PAGE_SIZE);
     info!("BootInfo at {:x?}", boot_info);
 
+    // The log can't be written once boot services are gone, and writing it goes through the file system, which
+    // allocates. It's saved before the memory map buffers are sized, so those allocations are already accounted for.
+    logger::finish();
+
+    // The final memory map can only be read after exiting boot services, at which point we can't allocate anymore.
+    // Reserve room for the regions now, with some slack for the entries that get split by the allocations we still make.
+    let region_capacity = boot::memory_map(MemoryType::LOADER_DATA).map_err(|_| LoaderError::MemoryMapUnavailable)?.len() + MEMORY_MAP_SLACK;
+    let regions = boot::allocate_pool(MemoryType::LOADER_DATA, region_capacity * size_of::<MemoryRegion>())
+        .map_err(|_| LoaderError::OutOfMemory("the memory map"))?;
+
+    // SAFETY: allocate_pool returned room for region_capacity regions, which are only read after copy_memory_map wrote them
+    let regions = unsafe { core::slice::from_raw_parts_mut(regions.as_ptr() as *mut MemoryRegion, region_capacity) };
+
+    let runtime_map = runtime::RuntimeMap::allocate()?;
+
+    timestamps.exit_boot_services = cpu::rdtsc();
+
+    // SAFETY: boot_info was written above
+    unsafe { (*boot_info).timestamps = timestamps };
+
+    Ok(Handoff { pml4, boot_info, regions, runtime_map, kernel_entry, stack_top, nx })
+}
+
+/// Exits boot services and jumps to the kernel
+fn boot(handoff: Handoff) -> ! {
+    let Handoff { pml4, boot_info, regions, runtime_map, kernel_entry, stack_top, nx } = handoff;
+
     // SAFETY: the uefi crate should handle exiting boot services safely
-    let _final_map = unsafe {
+    let final_map = unsafe {
         boot::exit_boot_services(None)
     };
-    
-    // SAFETY: the asm! block is safe by only moving a value to a register.
-    // SAFETY: elf.entry should contain the entrypoint to the kernel, so turning it into a fn pointer is okay
-    let kernel_main: extern "C" fn() -> ! = unsafe {
-        let pml4 = pml4 as *mut PageTable as u64;
-        asm!("mov cr3, {pml4}", pml4 = in(reg) pml4);
 
-        core::mem::transmute(elf.entry as *const ())
+    // Boot services are gone, so there's no error screen to show anymore and all that's left is to stop here
+    let len = match copy_memory_map(&final_map, regions) {
+        Ok(len) => len,
+        Err(err) => panic!("{}", err),
     };
-    
-    // SAFETY: this only moves the value into the register so is safe
+
+    // SAFETY: boot_info was filled in by prepare and nothing else has a reference to it
+    unsafe {
+        (*boot_info).memory_map = regions.as_ptr();
+        (*boot_info).memory_map_len = len;
+    }
+
+    // SAFETY: boot services are gone, cr3 still points to the firmware's page table, and the runtime regions are part
+    // of the physical memory mapped at HHDM_OFFSET
     unsafe {
-        asm!("mov rdi, {boot_info}", boot_info = in(reg) boot_info);
+        (*boot_info).efi_runtime_services = runtime_map.enter_virtual_mode(&final_map, HHDM_OFFSET);
     }
     
-    kernel_main();
+    // The kernel is built for a SysV target, so it's called with the SysV ABI rather than the UEFI target's
+    // default (Microsoft) calling convention: the boot info pointer goes in rdi. The stack is switched in the same
+    // asm! block as cr3, since the firmware's stack isn't something the kernel should keep running on.
+    //
+    // SAFETY: the new pml4 maps the loader's code (identity map), the kernel, its stack and the boot info, so
+    // execution can carry on after cr3 is loaded. kernel_entry is the relocated entrypoint of the kernel and never returns.
+    unsafe {
+        if nx {
+            cpu::enable_nx();
+        }
+        cpu::enable_write_protect();
+
+        let pml4 = pml4 as *mut PageTable as u64;
+        asm!(
+            "mov cr3, {pml4}",
+            "mov rsp, {stack}",
+            "xor ebp, ebp",
+            "call {entry}",
+            "ud2",
+            pml4 = in(reg) pml4,
+            stack = in(reg) stack_top,
+            entry = in(reg) kernel_entry,
+            in("rdi") boot_info,
+            options(noreturn),
+        );
+    }
 }
 
-fn load_kernel() -> Option<FileHandle> {
-    let image = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()).ok()?;
-    let mut fs = boot::open_protocol_exclusive::<SimpleFileSystem>(image.device()?).ok()?;
+/// Memory type used for the pages holding the kernel image, so the kernel can tell them apart from other loader allocations
+const KERNEL_MEMORY: MemoryType = MemoryType(KERNEL_MEMORY_TYPE);
+
+/// Where all physical memory gets mapped for the kernel, the start of the higher half
+const HHDM_OFFSET: u64 = 0xFFFF_8000_0000_0000;
+
+/// The least amount of physical address space that is mapped, whatever the memory map says
+const MIN_MAPPED_MEMORY: u64 = 0x1_0000_0000;
+
+/// Memory type used for the page tables, so the kernel knows they're still in use after the handoff
+const PAGE_TABLE_MEMORY: MemoryType = MemoryType(PAGE_TABLE_MEMORY_TYPE);
+
+/// Where the bottom of the kernel's stack gets mapped. It's in the last PML4 entry like the kernel, but below the
+/// top 2 GiB the kernel can be slid around in.
+const KERNEL_STACK_BOTTOM: u64 = 0xFFFF_FF80_0000_0000;
+
+/// Size of the kernel's initial stack, not counting the guard page below it
+const KERNEL_STACK_SIZE: u64 = 0x4_0000;
+
+/// Extra memory map entries to make room for between sizing the region buffer and exiting boot services
+const MEMORY_MAP_SLACK: usize = 16;
+
+fn region_kind(ty: MemoryType) -> MemoryRegionKind {
+    match ty {
+        MemoryType::CONVENTIONAL => MemoryRegionKind::Usable,
+        MemoryType::LOADER_CODE | MemoryType::LOADER_DATA => MemoryRegionKind::LoaderData,
+        MemoryType::BOOT_SERVICES_CODE | MemoryType::BOOT_SERVICES_DATA => MemoryRegionKind::BootServices,
+        MemoryType::RUNTIME_SERVICES_CODE | MemoryType::RUNTIME_SERVICES_DATA => MemoryRegionKind::RuntimeServices,
+        MemoryType::ACPI_RECLAIM => MemoryRegionKind::AcpiReclaimable,
+        MemoryType::ACPI_NON_VOLATILE => MemoryRegionKind::AcpiNvs,
+        MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE => MemoryRegionKind::Mmio,
+        KERNEL_MEMORY => MemoryRegionKind::Kernel,
+        modules::MODULE_MEMORY => MemoryRegionKind::BootModule,
+        PAGE_TABLE_MEMORY => MemoryRegionKind::PageTables,
+        _ => MemoryRegionKind::Reserved,
+    }
+}
 
-    let mut directory = fs.open_volume().ok()?;
+/// Converts the UEFI memory map into typed regions, returning how many entries of `out` were filled. Fails instead of
+/// leaving entries out if `out` is too small, a truncated map could hide memory the kernel must not touch.
+fn copy_memory_map(map: &MemoryMapOwned, out: &mut [MemoryRegion]) -> Result<usize, LoaderError> {
+    if map.len() > out.len() {
+        return Err(LoaderError::MemoryMapTooLarge { entries: map.len(), capacity: out.len() });
+    }
+
+    for (entry, region) in map.entries().zip(out.iter_mut()) {
+        *region = MemoryRegion {
+            start: entry.phys_start,
+            page_count: entry.page_count,
+            kind: region_kind(entry.ty),
+        };
+    }
 
-    directory.open(cstr16!("kernel.elf"), FileMode::Read, FileAttribute::empty()).ok()
+    Ok(map.len())
+}
+
+/// Finds the RSDP in the UEFI configuration table, preferring the ACPI 2.0 one (which points to the XSDT)
+fn find_rsdp() -> u64 {
+    uefi::system::with_config_table(|entries| {
+        entries.iter().find(|entry| entry.guid == ACPI2_GUID)
+            .or_else(|| entries.iter().find(|entry| entry.guid == ACPI_GUID))
+            .map(|entry| entry.address as u64)
+            .unwrap_or(0)
+    })
+}
+
+/// Reads the kernel of `entry`, going through its A/B slots if it has them. A pending update that can't be booted
+/// is given up on right away, instead of waiting for its tries to run out.
+fn read_entry_kernel(entry: &BootEntry, policy: VerifyPolicy) -> Option<(&'static [u8], SlotChoice)> {
+    if !entry.has_slots() {
+        return read_kernel(entry, &entry.kernel, policy).map(|kernel| (kernel, SlotChoice::UNSLOTTED));
+    }
+
+    let choice = slots::choose_slot();
+    if let Some(kernel) = read_kernel(entry, entry.kernel_path(choice.slot), policy) {
+        return Some((kernel, choice));
+    }
+
+    if !choice.trial {
+        return None;
+    }
+
+    let choice = slots::abandon_trial();
+    warn!("Update can't be booted, going back to slot {:?}", choice.slot);
+    read_kernel(entry, entry.kernel_path(choice.slot), policy).map(|kernel| (kernel, choice))
+}
+
+/// Reads the kernel at `path` and checks it according to `policy`, `None` if it can't be booted
+fn read_kernel(entry: &BootEntry, path: &str, policy: VerifyPolicy) -> Option<&'static [u8]> {
+    info!("Booting '{}' ({})", entry.title, path);
+
+    // The signature and digest are always of the uncompressed kernel, so they sit next to it without the `.lz4`
+    let path = path.strip_suffix(".lz4").unwrap_or(path);
+
+    // An LZ4 compressed copy is preferred, but a stale one that no longer passes the check shouldn't keep the
+    // uncompressed kernel next to it from booting
+    let compressed = format!("{}.lz4", path);
+    if let Some(kernel) = read_compressed_kernel(&compressed) {
+        if check_kernel(&compressed, path, &kernel, policy) {
+            // The segments are loaded straight from this buffer, so it stays around in pool memory until the handoff
+            return Some(kernel.leak());
+        }
+
+        warn!("Trying {} instead", path);
+    }
+
+    let Some(kernel) = CString16::try_from(path).ok().and_then(|path| fs::read_file(&path)) else {
+        warn!("Failed to read {}", path);
+        return None;
+    };
+
+    if !check_kernel(path, path, &kernel, policy) {
+        return None;
+    }
+
+    Some(kernel.leak())
+}
+
+/// Checks the kernel read from `file` against the signature or digest next to `path`, `false` if it can't be booted
+fn check_kernel(file: &str, path: &str, kernel: &[u8], policy: VerifyPolicy) -> bool {
+    match verify::verify_kernel(path, kernel, policy) {
+        Ok(Verification::Signed) => info!("Kernel signature is valid"),
+        Ok(Verification::Hashed) => info!("Kernel SHA-256 digest matches"),
+        Ok(Verification::Unverified) => warn!("Kernel has no signature or digest, booting it unverified"),
+        Err(err) => {
+            warn!("Refusing to boot {}: {}", file, err);
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Reads and decompresses the LZ4 compressed kernel at `path`. Decompressing is a lot faster than reading the whole
+/// ELF from slow ESP media.
+fn read_compressed_kernel(path: &str) -> Option<Vec<u8>> {
+    let file = CString16::try_from(path).ok().and_then(|path| fs::read_file(&path))?;
+
+    match lz4::decompress(&file) {
+        Ok(kernel) => {
+            info!("Decompressed {} ({} -> {} bytes)", path, file.len(), kernel.len());
+            Some(kernel)
+        }
+        Err(err) => {
+            warn!("Failed to decompress {}: {}", path, err);
+            None
+        }
+    }
 }
 
 #[repr(align(0x1000))]
@@ -199,62 +473,193 @@ macro_rules! page_table_index {
 
 const PAGE_PRESENT: u64 = 1 << 0;
 const PAGE_WRITE: u64 = 1 << 1;
+const PAGE_HUGE: u64 = 1 << 7;
+const PAGE_NO_EXECUTE: u64 = 1 << 63;
+
+const HUGE_PAGE_2M: u64 = 0x20_0000;
+const HUGE_PAGE_1G: u64 = 0x4000_0000;
 
-fn allocate_table() -> &'static mut PageTable {
-    let addr = boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1).unwrap();
+const PAGE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+fn allocate_table() -> Result<&'static mut PageTable, LoaderError> {
+    let addr = boot::allocate_pages(AllocateType::AnyPages, PAGE_TABLE_MEMORY, 1).map_err(|_| LoaderError::OutOfMemory("a page table"))?;
     let addr = addr.as_ptr() as *mut PageTable;
     
     // SAFETY: allocate_pages returns a valid pointer, so dereferencing it is okay
     let out = unsafe { &mut *addr };
     out.entries.fill(0);
 
-    out
+    Ok(out)
 }
 
-unsafe fn get_or_allocate_table(table: &mut PageTable, idx: usize, flags: u64) -> &'static mut PageTable {
+unsafe fn get_or_allocate_table(table: &mut PageTable, idx: usize, flags: u64) -> Result<&'static mut PageTable, LoaderError> {
     if table.entries[idx] & PAGE_PRESENT != 0 {
-        let other = table.entries[idx] & !0xFFF;
+        let other = table.entries[idx] & PAGE_ADDRESS_MASK;
         let other = other as *mut PageTable;
         
         // NOT SAFE: we can't guarantee that the pointer is valid, but we're required to assume that it is. Hence, why this function is labeled as unsafe
-        &mut *other
+        Ok(&mut *other)
     } else {
-        let other = allocate_table();
+        let other = allocate_table()?;
         table.entries[idx] = other as *mut PageTable as u64 | flags;
-        other
+        Ok(other)
     }
 }
 
-fn map_page(pml4: &mut PageTable, virt: u64, phys: u64, flags: u64) {
+fn map_page(pml4: &mut PageTable, virt: u64, phys: u64, flags: u64) -> Result<(), LoaderError> {
+    // Permissions are only applied on the last level, so the tables above it have to allow everything.
     // SAFETY: thus far, the pml4 should have only been built by this function, so get_or_allocate_table gets the values it's expecting and is thus safe to use
-    let pdpt = unsafe { get_or_allocate_table(pml4, page_table_index!(virt, 3), flags | PAGE_PRESENT) };
-    let pd = unsafe { get_or_allocate_table(pdpt, page_table_index!(virt, 2), flags | PAGE_PRESENT) };
-    let pt =  unsafe { get_or_allocate_table(pd, page_table_index!(virt, 1), flags | PAGE_PRESENT) };
+    let pdpt = unsafe { get_or_allocate_table(pml4, page_table_index!(virt, 3), PAGE_WRITE | PAGE_PRESENT)? };
+    let pd = unsafe { get_or_allocate_table(pdpt, page_table_index!(virt, 2), PAGE_WRITE | PAGE_PRESENT)? };
+    let pt =  unsafe { get_or_allocate_table(pd, page_table_index!(virt, 1), PAGE_WRITE | PAGE_PRESENT)? };
 
     pt.entries[page_table_index!(virt, 0)] = (phys & !0xFFF) | PAGE_PRESENT | flags;
+
+    Ok(())
 }
 
-fn map_static(pml4: &mut PageTable) {
-    const PML4_VIRT: u64 = 0xFFFF_FDFF_FFFF_D000;
-    const VIRT: u64 = 0xFFFF_FDFF_FFFF_E000;
+/// Looks up the last level entry for `virt`, or `None` if it isn't mapped
+fn page_entry(pml4: &PageTable, virt: u64) -> Option<u64> {
+    let mut table = pml4;
 
-    let pdpt = unsafe { get_or_allocate_table(pml4, page_table_index!(VIRT, 3), PAGE_WRITE | PAGE_PRESENT) };
-    let pd = unsafe { get_or_allocate_table(pdpt, page_table_index!(VIRT, 2), PAGE_WRITE | PAGE_PRESENT) };
-    let pt =  unsafe { get_or_allocate_table(pd, page_table_index!(VIRT, 1), PAGE_WRITE | PAGE_PRESENT) };
-    
-    let phys = pt as *const PageTable as u64;
+    for depth in (1..=3).rev() {
+        let entry = table.entries[page_table_index!(virt, depth)];
+        if entry & PAGE_PRESENT == 0 {
+            return None;
+        }
 
-    pt.entries[page_table_index!(PML4_VIRT, 0)] = ((pml4 as *const PageTable as u64) & !0xFFF) | PAGE_PRESENT | PAGE_WRITE;
-    pt.entries[page_table_index!(VIRT, 0)] = (phys & !0xFFF) | PAGE_PRESENT | PAGE_WRITE;
+        // SAFETY: every table reachable from the pml4 was allocated by allocate_table, which is identity mapped
+        table = unsafe { &*((entry & PAGE_ADDRESS_MASK) as *const PageTable) };
+    }
+
+    let entry = table.entries[page_table_index!(virt, 0)];
+    (entry & PAGE_PRESENT != 0).then_some(entry)
 }
 
-#[repr(C)]
-struct UEFIBootInfo {
-    framebuffer: *mut u32,
-    framebuffer_size: usize,
-    framebuffer_width: usize,
-    framebuffer_height: usize,
+/// Loads a PT_LOAD segment `slide` bytes above its link address into freshly allocated pages and maps them with
+/// the permissions from `p_flags`. Everything past `p_filesz` (the BSS) is left zeroed.
+fn load_segment(pml4: &mut PageTable, kernel_file: &[u8], phdr: &ProgramHeader, slide: u64, nx: bool) -> Result<(), LoaderError> {
+    let writable = phdr.p_flags & PF_W != 0;
+    let executable = phdr.p_flags & PF_X != 0;
+    if writable && executable {
+        return Err(LoaderError::WritableExecutableSegment { vaddr: phdr.p_vaddr + slide });
+    }
+
+    let data = phdr.p_offset.checked_add(phdr.p_filesz)
+        .filter(|_| phdr.p_filesz <= phdr.p_memsz)
+        .and_then(|end| kernel_file.get(phdr.p_offset as usize..end as usize))
+        .ok_or(LoaderError::SegmentOutsideFile { vaddr: phdr.p_vaddr + slide })?;
 
-    pub memory_bitmap: *mut u8,
-    pub memory_bitmap_size: usize,
-}
\ No newline at end of file
+    let mut flags = 0;
+    if writable {
+        flags |= PAGE_WRITE;
+    }
+    if nx && !executable {
+        flags |= PAGE_NO_EXECUTE;
+    }
+
+    let vaddr = phdr.p_vaddr + slide;
+    let start = vaddr & !(PAGE_SIZE as u64 - 1);
+    let end = (vaddr + phdr.p_memsz).next_multiple_of(PAGE_SIZE as u64);
+
+    for virt in (start..end).step_by(PAGE_SIZE) {
+        let (phys, flags) = match page_entry(pml4, virt) {
+            // Segments that don't start or end on a page boundary can share a page with another segment,
+            // in which case the page gets the most permissive combination of both
+            Some(entry) => {
+                let flags = ((entry | flags) & PAGE_WRITE) | (entry & flags & PAGE_NO_EXECUTE);
+                if flags & PAGE_WRITE != 0 && (!nx || flags & PAGE_NO_EXECUTE == 0) {
+                    warn!("Kernel page {:#x} is shared by segments and ends up both writable and executable", virt);
+                }
+
+                (entry & PAGE_ADDRESS_MASK, flags)
+            }
+            None => {
+                let page = boot::allocate_pages(AllocateType::AnyPages, KERNEL_MEMORY, 1).map_err(|_| LoaderError::OutOfMemory("the kernel"))?;
+
+                // SAFETY: allocate_pages returned a valid page, which is identity mapped while boot services are active
+                unsafe { page.as_ptr().write_bytes(0, PAGE_SIZE) };
+
+                (page.as_ptr() as u64, flags)
+            }
+        };
+
+        map_page(pml4, virt, phys, flags)?;
+    }
+
+    write_kernel_memory(pml4, vaddr, data)
+}
+
+/// Applies the kernel's dynamic relocations for a kernel loaded `slide` bytes above its link address.
+/// A static PIE only ever has R_X86_64_RELATIVE relocations, anything else means the kernel was built wrong.
+fn apply_relocations(pml4: &PageTable, elf: &Elf, slide: u64) -> Result<(), LoaderError> {
+    for reloc in elf.dynrelas.iter() {
+        match reloc.r_type {
+            R_X86_64_NONE => {}
+            R_X86_64_RELATIVE => {
+                let value = reloc.r_addend.unwrap_or(0).wrapping_add_unsigned(slide) as u64;
+                write_kernel_memory(pml4, reloc.r_offset + slide, &value.to_le_bytes())?;
+            }
+            kind => return Err(LoaderError::UnsupportedRelocation { kind, offset: reloc.r_offset }),
+        }
+    }
+
+    Ok(())
+}
+
+/// Allocates the kernel's stack and maps it at [`KERNEL_STACK_BOTTOM`], leaving the page below it unmapped as a guard page.
+/// Returns the bottom and top of the stack.
+fn allocate_stack(pml4: &mut PageTable, nx: bool) -> Result<(u64, u64), LoaderError> {
+    let pages = (KERNEL_STACK_SIZE / PAGE_SIZE as u64) as usize;
+    let stack = boot::allocate_pages(AllocateType::AnyPages, KERNEL_MEMORY, pages).map_err(|_| LoaderError::OutOfMemory("the kernel stack"))?;
+
+    // SAFETY: allocate_pages returned `pages` valid pages, which are identity mapped while boot services are active
+    unsafe { stack.as_ptr().write_bytes(0, KERNEL_STACK_SIZE as usize) };
+
+    let flags = if nx { PAGE_WRITE | PAGE_NO_EXECUTE } else { PAGE_WRITE };
+    for offset in (0..KERNEL_STACK_SIZE).step_by(PAGE_SIZE) {
+        map_page(pml4, KERNEL_STACK_BOTTOM + offset, stack.as_ptr() as u64 + offset, flags)?;
+    }
+
+    Ok((KERNEL_STACK_BOTTOM, KERNEL_STACK_BOTTOM + KERNEL_STACK_SIZE))
+}
+
+/// Copies `data` to the kernel's virtual address `virt`, which has to be mapped already.
+/// The pages backing the kernel aren't contiguous, so this goes one page at a time.
+fn write_kernel_memory(pml4: &PageTable, virt: u64, data: &[u8]) -> Result<(), LoaderError> {
+    let mut copied = 0;
+    while copied < data.len() {
+        let virt = virt + copied as u64;
+        let len = (PAGE_SIZE - virt as usize % PAGE_SIZE).min(data.len() - copied);
+        let phys = (page_entry(pml4, virt).ok_or(LoaderError::RelocationOutsideImage { offset: virt })? & PAGE_ADDRESS_MASK) + virt % PAGE_SIZE as u64;
+
+        // SAFETY: phys is the identity mapped page backing virt, and len never crosses the end of that page
+        unsafe { data[copied..].as_ptr().copy_to_nonoverlapping(phys as *mut u8, len) };
+
+        copied += len;
+    }
+
+    Ok(())
+}
+
+/// Maps physical memory from 0 up to `end` at `offset`, using 1 GiB pages if `huge_1g` is set and 2 MiB pages otherwise.
+/// Nothing is marked non-executable, since the UEFI runtime services are called through these mappings.
+fn map_physical_memory(pml4: &mut PageTable, offset: u64, end: u64, huge_1g: bool) -> Result<(), LoaderError> {
+    let page_size = if huge_1g { HUGE_PAGE_1G } else { HUGE_PAGE_2M };
+
+    for phys in (0..end.next_multiple_of(page_size)).step_by(page_size as usize) {
+        let virt = offset + phys;
+
+        // SAFETY: this range is only ever mapped by this function, so every table on the way is one get_or_allocate_table made
+        let pdpt = unsafe { get_or_allocate_table(pml4, page_table_index!(virt, 3), PAGE_WRITE | PAGE_PRESENT)? };
+
+        if huge_1g {
+            pdpt.entries[page_table_index!(virt, 2)] = phys | PAGE_HUGE | PAGE_WRITE | PAGE_PRESENT;
+        } else {
+            let pd = unsafe { get_or_allocate_table(pdpt, page_table_index!(virt, 2), PAGE_WRITE | PAGE_PRESENT)? };
+            pd.entries[page_table_index!(virt, 1)] = phys | PAGE_HUGE | PAGE_WRITE | PAGE_PRESENT;
+        }
+    }
+
+    Ok(())
+}
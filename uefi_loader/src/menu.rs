@@ -0,0 +1,118 @@
+use crate::config::BootConfig;
+use boot_protocol::BootSlot;
+use core::fmt::Write;
+use log::warn;
+use uefi::boot::{self, EventType, TimerTrigger, Tpl};
+use uefi::proto::console::text::{Color, Key, ScanCode};
+use uefi::system;
+
+/// One second, in the 100ns units used by UEFI timers
+const TIMER_TICK: u64 = 10_000_000;
+
+/// Shows the boot menu and returns the index of the chosen entry.
+///
+/// The default entry is booted once the timeout runs out, pressing any key stops the countdown.
+/// With a timeout of 0 the menu is skipped entirely.
+pub fn select_entry(config: &BootConfig) -> usize {
+    if config.timeout == 0 {
+        return config.default;
+    }
+
+    let Some(key_event) = system::with_stdin(|stdin| stdin.wait_for_key_event()) else {
+        warn!("No keyboard input available, booting the default entry");
+        return config.default;
+    };
+
+    // SAFETY: the event has no notify function, so there's nothing that could be called with invalid state
+    let timer = match unsafe { boot::create_event(EventType::TIMER, Tpl::APPLICATION, None, None) } {
+        Ok(timer) => timer,
+        Err(_) => {
+            warn!("Failed to create the boot menu timer, booting the default entry");
+            return config.default;
+        }
+    };
+
+    if boot::set_timer(&timer, TimerTrigger::Periodic(TIMER_TICK)).is_err() {
+        warn!("Failed to start the boot menu timer, booting the default entry");
+        let _ = boot::close_event(timer);
+        return config.default;
+    }
+
+    let mut selected = config.default;
+    let mut remaining = Some(config.timeout);
+    let mut events = [key_event, timer];
+
+    let chosen = loop {
+        draw_menu(config, selected, remaining);
+
+        match boot::wait_for_event(&mut events) {
+            Ok(0) => {
+                let Ok(Some(key)) = system::with_stdin(|stdin| stdin.read_key()) else {
+                    continue;
+                };
+
+                // Any key press means the user is picking an entry themselves
+                remaining = None;
+
+                match key {
+                    Key::Special(ScanCode::UP) => selected = selected.checked_sub(1).unwrap_or(config.entries.len() - 1),
+                    Key::Special(ScanCode::DOWN) => selected = (selected + 1) % config.entries.len(),
+                    Key::Printable(c) if char::from(c) == '\r' || char::from(c) == '\n' => break selected,
+                    _ => {}
+                }
+            }
+            Ok(_) => {
+                if let Some(seconds) = remaining {
+                    if seconds <= 1 {
+                        break config.default;
+                    }
+                    remaining = Some(seconds - 1);
+                }
+            }
+            Err(_) => break selected,
+        }
+    };
+
+    let [_, timer] = events;
+    let _ = boot::set_timer(&timer, TimerTrigger::Cancel);
+    let _ = boot::close_event(timer);
+
+    system::with_stdout(|stdout| {
+        let _ = stdout.set_color(Color::LightGray, Color::Black);
+        let _ = stdout.clear();
+    });
+
+    chosen
+}
+
+fn draw_menu(config: &BootConfig, selected: usize, remaining: Option<u64>) {
+    system::with_stdout(|stdout| {
+        let _ = stdout.set_color(Color::LightGray, Color::Black);
+        let _ = stdout.clear();
+
+        let _ = writeln!(stdout, "GroveOS boot menu\n");
+
+        for (i, entry) in config.entries.iter().enumerate() {
+            if i == selected {
+                let _ = stdout.set_color(Color::Black, Color::LightGray);
+                let _ = writeln!(stdout, " > {} ", entry.title);
+                let _ = stdout.set_color(Color::LightGray, Color::Black);
+            } else {
+                let _ = writeln!(stdout, "   {} ", entry.title);
+            }
+        }
+
+        let entry = &config.entries[selected];
+        if entry.has_slots() {
+            let _ = writeln!(stdout, "\nKernel:  {} (A), {} (B)", entry.kernel_path(BootSlot::A), entry.kernel_path(BootSlot::B));
+        } else {
+            let _ = writeln!(stdout, "\nKernel:  {}", entry.kernel);
+        }
+        let _ = writeln!(stdout, "Cmdline: {}", entry.cmdline.as_deref().unwrap_or(""));
+
+        let _ = writeln!(stdout, "\nUse the arrow keys to select an entry and Enter to boot it.");
+        if let Some(seconds) = remaining {
+            let _ = writeln!(stdout, "Booting '{}' in {}s...", config.default_entry().title, seconds);
+        }
+    });
+}
@@ -0,0 +1,43 @@
+use crate::config::BootEntry;
+use crate::fs::read_file_to_pages;
+use alloc::vec::Vec;
+use boot_protocol::{BootModule, MODULE_MEMORY_TYPE};
+use log::{info, warn};
+use uefi::boot::MemoryType;
+use uefi::CString16;
+
+/// Memory type used for the pages holding boot modules, so the kernel knows not to hand them out
+pub const MODULE_MEMORY: MemoryType = MemoryType(MODULE_MEMORY_TYPE);
+
+/// Loads the initrd and modules of `entry` into page aligned memory.
+///
+/// Files that can't be read are skipped with a warning, it's up to the kernel to decide whether it can boot without them.
+pub fn load_modules(entry: &BootEntry) -> Vec<BootModule> {
+    let initrd = entry.initrd.iter().map(|path| (path.as_str(), "initrd"));
+    let modules = entry.modules.iter().map(|path| (path.as_str(), file_name(path)));
+
+    initrd.chain(modules)
+        .filter_map(|(path, name)| {
+            let module = load_module(path, name);
+            if module.is_none() {
+                warn!("Failed to load boot module {}, skipping it", path);
+            }
+
+            module
+        })
+        .collect()
+}
+
+fn load_module(path: &str, name: &str) -> Option<BootModule> {
+    let path = CString16::try_from(path).ok()?;
+    let (memory, size) = read_file_to_pages(&path, MODULE_MEMORY)?;
+
+    info!("Loaded boot module '{}' ({} bytes) @ {:x}", name, size, memory.as_ptr() as u64);
+
+    Some(BootModule::new(memory.as_ptr() as u64, size as u64, name))
+}
+
+/// The last component of an ESP path like `\drivers\ahci.ko`
+fn file_name(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
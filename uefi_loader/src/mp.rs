@@ -0,0 +1,56 @@
+use alloc::vec::Vec;
+use boot_protocol::ProcessorInfo;
+use log::{info, warn};
+use uefi::boot;
+use uefi::proto::pi::mp::MpServices;
+
+/// Asks the firmware's MP services for every processor and its APIC ID.
+///
+/// Firmware without MP services (or where they fail) gets an empty list, the kernel falls back to ACPI and CPUID then.
+pub fn read_processors() -> Vec<ProcessorInfo> {
+    let Some(mp) = boot::get_handle_for_protocol::<MpServices>().ok()
+        .and_then(|handle| boot::open_protocol_exclusive::<MpServices>(handle).ok()) else {
+        warn!("No MP services, the kernel will have to find the processors itself");
+        return Vec::new();
+    };
+
+    let Ok(count) = mp.get_number_of_processors() else {
+        warn!("Failed to get the number of processors from the MP services");
+        return Vec::new();
+    };
+
+    let processors: Vec<ProcessorInfo> = (0..count.total)
+        .filter_map(|index| {
+            let info = mp.get_processor_info(index).ok();
+            if info.is_none() {
+                warn!("Failed to get info for processor {}, skipping it", index);
+            }
+
+            info
+        })
+        .map(|info| {
+            let mut flags = 0;
+            if info.is_bsp() {
+                flags |= ProcessorInfo::PROCESSOR_BSP;
+            }
+            if info.is_enabled() {
+                flags |= ProcessorInfo::PROCESSOR_ENABLED;
+            }
+            if info.is_healthy() {
+                flags |= ProcessorInfo::PROCESSOR_HEALTHY;
+            }
+
+            ProcessorInfo {
+                apic_id: info.processor_id as u32,
+                flags,
+                package: info.location.package,
+                core: info.location.core,
+                thread: info.location.thread,
+            }
+        })
+        .collect();
+
+    info!("MP services report {} processors ({} enabled)", count.total, count.enabled);
+
+    processors
+}
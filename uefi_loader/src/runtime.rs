@@ -0,0 +1,65 @@
+use crate::error::LoaderError;
+use uefi::boot::{self, MemoryType};
+use uefi::mem::memory_map::{MemoryAttribute, MemoryDescriptor, MemoryMap, MemoryMapOwned};
+use uefi::runtime;
+
+/// Buffer for the runtime regions passed to `SetVirtualAddressMap`. It has to be allocated while boot services are
+/// still around, but is only filled in after exiting them.
+pub struct RuntimeMap {
+    descriptors: &'static mut [MemoryDescriptor],
+}
+
+impl RuntimeMap {
+    /// Makes room for as many runtime regions as the current memory map has entries, which is always enough
+    pub fn allocate() -> Result<Self, LoaderError> {
+        let capacity = boot::memory_map(MemoryType::LOADER_DATA).map_err(|_| LoaderError::MemoryMapUnavailable)?.len() + crate::MEMORY_MAP_SLACK;
+        let buffer = boot::allocate_pool(MemoryType::LOADER_DATA, capacity * size_of::<MemoryDescriptor>())
+            .map_err(|_| LoaderError::OutOfMemory("the runtime memory map"))?;
+        let buffer = buffer.as_ptr() as *mut MemoryDescriptor;
+
+        // SAFETY: allocate_pool returned room for `capacity` descriptors, which are all initialized before use
+        let descriptors = unsafe {
+            for i in 0..capacity {
+                buffer.add(i).write(MemoryDescriptor::default());
+            }
+
+            core::slice::from_raw_parts_mut(buffer, capacity)
+        };
+
+        Ok(Self { descriptors })
+    }
+
+    /// Switches the runtime services over to virtual addressing, with every runtime region at `offset` plus its
+    /// physical address. Returns the virtual address of the runtime services table, or 0 if the switch failed.
+    ///
+    /// Nothing gets logged in here, the console is gone once boot services have been exited.
+    ///
+    /// # Safety
+    /// Has to be called once, after exiting boot services and while the firmware's identity mapping is still in use.
+    /// Every runtime region has to be mapped at `offset` in the page table the kernel calls the runtime services with.
+    pub unsafe fn enter_virtual_mode(mut self, final_map: &MemoryMapOwned, offset: u64) -> u64 {
+        let Some(system_table) = uefi::table::system_table_raw() else {
+            return 0;
+        };
+
+        let mut len = 0;
+        for entry in final_map.entries().filter(|entry| entry.att.contains(MemoryAttribute::RUNTIME)) {
+            let Some(descriptor) = self.descriptors.get_mut(len) else {
+                return 0;
+            };
+
+            *descriptor = *entry;
+            descriptor.virt_start = entry.phys_start + offset;
+            len += 1;
+        }
+
+        // Read before the switch: afterwards the table holds virtual addresses, which aren't mapped yet
+        let runtime_services = (*system_table.as_ptr()).runtime_services as u64;
+        let system_table_virt = (system_table.as_ptr() as u64 + offset) as *const _;
+
+        match runtime::set_virtual_address_map(&mut self.descriptors[..len], system_table_virt) {
+            Ok(()) => runtime_services + offset,
+            Err(_) => 0,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use boot_protocol::{BootSlot, BootState, BOOT_STATE_VARIABLE, GROVE_VENDOR_GUID};
+use log::{info, warn};
+use uefi::runtime::{self, VariableAttributes, VariableVendor};
+use uefi::{CString16, Guid};
+
+const VENDOR: VariableVendor = VariableVendor(Guid::from_bytes(GROVE_VENDOR_GUID));
+
+/// Which slot to boot, and whether it's an update that's still being tried
+#[derive(Copy, Clone, Debug)]
+pub struct SlotChoice {
+    pub slot: BootSlot,
+    pub trial: bool,
+}
+
+impl SlotChoice {
+    /// For entries without slots
+    pub const UNSLOTTED: Self = Self { slot: BootSlot::None, trial: false };
+}
+
+/// Picks the slot to boot from the boot state. A pending update is booted as long as it has tries left, and every
+/// attempt takes one try, so a kernel that never confirms itself stops being booted after a few resets.
+pub fn choose_slot() -> SlotChoice {
+    let mut state = read_state();
+
+    if state.pending == BootSlot::None {
+        return SlotChoice { slot: state.active, trial: false };
+    }
+
+    if state.tries_left == 0 {
+        warn!("Update in slot {:?} was never confirmed, going back to slot {:?}", state.pending, state.active);
+        state.pending = BootSlot::None;
+        write_state(&state);
+
+        return SlotChoice { slot: state.active, trial: false };
+    }
+
+    state.tries_left -= 1;
+    if !write_state(&state) {
+        // Without counting the attempt, a broken update would be booted forever
+        warn!("Failed to count the boot attempt, not trying the update in slot {:?}", state.pending);
+        return SlotChoice { slot: state.active, trial: false };
+    }
+
+    info!("Trying update in slot {:?} ({} tries left after this one)", state.pending, state.tries_left);
+    SlotChoice { slot: state.pending, trial: true }
+}
+
+/// Drops the pending update, for when its kernel can't even be loaded. Returns the slot to boot instead.
+pub fn abandon_trial() -> SlotChoice {
+    let mut state = read_state();
+    state.pending = BootSlot::None;
+    state.tries_left = 0;
+    write_state(&state);
+
+    SlotChoice { slot: state.active, trial: false }
+}
+
+fn read_state() -> BootState {
+    let name = CString16::try_from(BOOT_STATE_VARIABLE).unwrap();
+    let mut buffer = [0u8; BootState::SIZE];
+
+    match runtime::get_variable(&name, &VENDOR, &mut buffer) {
+        Ok((data, _)) => BootState::from_bytes(data).unwrap_or_else(|| {
+            warn!("{} is invalid, ignoring it", BOOT_STATE_VARIABLE);
+            BootState::initial()
+        }),
+        Err(_) => BootState::initial(),
+    }
+}
+
+fn write_state(state: &BootState) -> bool {
+    let name = CString16::try_from(BOOT_STATE_VARIABLE).unwrap();
+    // The kernel updates the state through the runtime services, so the variable has to stay visible to it
+    let attributes = VariableAttributes::NON_VOLATILE | VariableAttributes::BOOTSERVICE_ACCESS | VariableAttributes::RUNTIME_ACCESS;
+
+    let written = runtime::set_variable(&name, &VENDOR, attributes, &state.to_bytes()).is_ok();
+    if !written {
+        warn!("Failed to write {}", BOOT_STATE_VARIABLE);
+    }
+
+    written
+}
@@ -0,0 +1,121 @@
+use crate::fs::read_file;
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use ed25519_compact::{PublicKey, Signature};
+use sha2::{Digest, Sha256};
+use uefi::CString16;
+
+/// Hex encoded Ed25519 public key that kernel signatures are checked against, set when building the loader
+const PUBLIC_KEY: Option<&str> = option_env!("GROVE_KERNEL_PUBKEY");
+
+/// How strict the loader is about checking kernels before booting them, set with `verify =` in `grove.cfg`.
+/// Only used by loaders built without a public key, those always require a valid signature.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VerifyPolicy {
+    /// Check whatever signature or digest sits next to the kernel, but boot kernels without either
+    Auto,
+    /// Only boot kernels with a matching digest or a valid signature
+    Hash,
+    /// Only boot kernels with a valid signature
+    Signature,
+}
+
+/// How a kernel was checked
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Verification {
+    Signed,
+    Hashed,
+    Unverified,
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The policy asks for a signature or digest, and there's none next to the kernel
+    Missing,
+    /// The policy asks for a signature, but the loader was built without a public key
+    NoPublicKey,
+    /// The public key the loader was built with isn't a hex encoded Ed25519 public key
+    InvalidPublicKey,
+    /// A `.sig` or `.sha256` file exists but isn't in the expected format
+    InvalidSidecar,
+    BadSignature,
+    HashMismatch,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            VerifyError::Missing => write!(f, "no signature or digest found"),
+            VerifyError::NoPublicKey => write!(f, "the loader was built without a public key"),
+            VerifyError::InvalidPublicKey => write!(f, "the loader was built with a malformed public key"),
+            VerifyError::InvalidSidecar => write!(f, "malformed signature or digest file"),
+            VerifyError::BadSignature => write!(f, "signature doesn't match"),
+            VerifyError::HashMismatch => write!(f, "SHA-256 digest doesn't match"),
+        }
+    }
+}
+
+/// Checks `kernel`, read from `path`, against the files next to it on the ESP:
+/// - `<path>.sig`: a raw 64 byte Ed25519 signature of the whole file, checked against the built-in public key
+/// - `<path>.sha256`: the hex SHA-256 digest of the whole file, as written by `sha256sum`
+///
+/// When the loader was built with a public key, the policy comes from an unauthenticated file and is ignored: a valid
+/// signature is required and digests aren't accepted. Otherwise a signature or digest that's present but doesn't
+/// match always fails, whatever the policy.
+pub fn verify_kernel(path: &str, kernel: &[u8], policy: VerifyPolicy) -> Result<Verification, VerifyError> {
+    if let Some(public_key) = PUBLIC_KEY {
+        let public_key = decode_hex::<{ PublicKey::BYTES }>(public_key)
+            .map(PublicKey::new)
+            .ok_or(VerifyError::InvalidPublicKey)?;
+        let signature = read_sidecar(path, "sig").ok_or(VerifyError::Missing)?;
+        let signature = Signature::from_slice(&signature).map_err(|_| VerifyError::InvalidSidecar)?;
+
+        return match public_key.verify(kernel, &signature) {
+            Ok(()) => Ok(Verification::Signed),
+            Err(_) => Err(VerifyError::BadSignature),
+        };
+    }
+
+    if policy == VerifyPolicy::Signature {
+        return Err(VerifyError::NoPublicKey);
+    }
+
+    if let Some(digest) = read_sidecar(path, "sha256") {
+        let expected = core::str::from_utf8(&digest).ok()
+            .and_then(|digest| digest.split_whitespace().next())
+            .and_then(decode_hex::<32>)
+            .ok_or(VerifyError::InvalidSidecar)?;
+
+        return if Sha256::digest(kernel).as_slice() == expected {
+            Ok(Verification::Hashed)
+        } else {
+            Err(VerifyError::HashMismatch)
+        };
+    }
+
+    match policy {
+        VerifyPolicy::Auto => Ok(Verification::Unverified),
+        _ => Err(VerifyError::Missing),
+    }
+}
+
+fn read_sidecar(path: &str, extension: &str) -> Option<Vec<u8>> {
+    let path = CString16::try_from(format!("{}.{}", path, extension).as_str()).ok()?;
+    read_file(&path)
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != N * 2 {
+        return None;
+    }
+
+    let mut out = [0u8; N];
+    for (byte, pair) in out.iter_mut().zip(hex.chunks_exact(2)) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+
+    Some(out)
+}